@@ -0,0 +1,207 @@
+//! The board top page in the layout of 2channel's index.html.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use memchr::memchr;
+
+use super::Topics;
+use bbs::BoardRef;
+use bbs::dat::{self, Record};
+use setting::common::{ContentsNumber, LineNumber, MaxMenuThread, ThreadNumber, Title};
+
+const DEFAULT_THREAD_NUMBER: u32 = 10;
+const DEFAULT_CONTENTS_NUMBER: u32 = 10;
+const DEFAULT_MAX_MENU_THREAD: u32 = 100;
+
+// "【"
+const LENTICULAR_OPEN: &[u8] = b"\x81\x79";
+// "】"
+const LENTICULAR_CLOSE: &[u8] = b"\x81\x7A";
+// "："
+const COLON: &[u8] = b"\x81\x46";
+// "ここ壊れてます"
+const BROKEN: &[u8] = b"\x82\xB1\x82\xB1\x89\xF3\x82\xEA\x82\xC4\x82\xDC\x82\xB7";
+
+/// The topics shown in index.html, copied out of `Topics` so that the dats
+/// can be read without holding the lock.
+pub(in bbs) struct Snapshot {
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    key: u64,
+    title: Box<[u8]>,
+    post_count: usize,
+}
+
+impl Snapshot {
+    pub(in bbs) fn new(board: &BoardRef, topics: &Topics) -> Self {
+        let (digests, menu) = counts(board);
+        let entries = topics.iter()
+            .take(cmp::max(digests, menu))
+            .map(|(key, t)| Entry { key, title: t.title().into(), post_count: t.post_count() })
+            .collect();
+        Snapshot { entries }
+    }
+}
+
+/// Returns the number of the digests and that of the topics in the menu.
+fn counts(board: &BoardRef) -> (usize, usize) {
+    let settings = board.settings();
+    let digests = settings.get::<ThreadNumber>().cloned()
+        .unwrap_or(DEFAULT_THREAD_NUMBER) as usize;
+    let menu = settings.get::<MaxMenuThread>().cloned()
+        .unwrap_or(DEFAULT_MAX_MENU_THREAD) as usize;
+    (digests, menu)
+}
+
+pub(in bbs) fn make(board: &BoardRef, snapshot: &Snapshot, html: &mut Vec<u8>) {
+    let settings = board.settings();
+    let title = settings.get::<Title>().map_or(board.id().as_bytes(), |t| &**t);
+    let (digests, menu) = counts(board);
+
+    html.clear();
+
+    html.extend_from_slice(b"<html><head>\
+        <meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">\
+        <title>");
+    html.extend_from_slice(title);
+    html.extend_from_slice(b"</title></head>\n<body>\n<div class=\"title\"><h1>");
+    html.extend_from_slice(title);
+    html.extend_from_slice(b"</h1></div>\n");
+
+    // The thread list:
+    html.extend_from_slice(b"<div class=\"menu\"><a name=\"menu\"></a><small>\n");
+    for (i, t) in snapshot.entries.iter().take(menu).enumerate() {
+        let (n, key) = (i + 1, t.key);
+        if i < digests {
+            write!(html, "<a href=\"#{}\">", n).unwrap();
+        } else {
+            write!(html, "<a href=\"../test/read.cgi/{}/{}/l50\">", board.id(), key).unwrap();
+        }
+        write!(html, "{}: ", n).unwrap();
+        html.extend_from_slice(&t.title);
+        write!(html, " ({})</a>\n", t.post_count).unwrap();
+    }
+    html.extend_from_slice(b"</small></div>\n");
+
+    // The digests of the newest topics:
+    for (i, t) in snapshot.entries.iter().take(digests).enumerate() {
+        let (n, key) = (i + 1, t.key);
+        write!(html, "<div class=\"thread\"><a name=\"{}\"></a><b>", n).unwrap();
+        html.extend_from_slice(LENTICULAR_OPEN);
+        write!(html, "{}:{}", n, t.post_count).unwrap();
+        html.extend_from_slice(LENTICULAR_CLOSE);
+        html.extend_from_slice(b"<font size=\"+2\" color=\"red\">");
+        html.extend_from_slice(&t.title);
+        html.extend_from_slice(b"</font></b>\n<dl>\n");
+
+        // Posts are omitted on failure so that the rest of the page is
+        // still available.
+        let mut dat = Vec::new();
+        let read = File::open(board.dat_path(key))
+            .and_then(|mut f| f.read_to_end(&mut dat));
+        if read.is_ok() {
+            write_digest(board, key, &dat, html);
+        }
+
+        html.extend_from_slice(b"</dl>\n");
+        write!(html, "<a href=\"../test/read.cgi/{}/{}/\">", board.id(), key).unwrap();
+        // "全部読む"
+        html.extend_from_slice(b"\x91\x53\x95\x94\x93\xC7\x82\xDE</a> ");
+        write!(html, "<a href=\"../test/read.cgi/{}/{}/l50\">", board.id(), key).unwrap();
+        // "最新50"
+        html.extend_from_slice(b"\x8D\xC5\x90\x5650</a> ");
+        write!(html, "<a href=\"../test/read.cgi/{}/{}/1-100\">1-100</a> ", board.id(), key)
+            .unwrap();
+        // "板のトップ"
+        html.extend_from_slice(b"<a href=\"#menu\">\x94\xC2\x82\xCC\x83\x67\x83\x62\x83\x76</a> ");
+        // "リロード"
+        html.extend_from_slice(b"<a href=\"./\">\x83\x8A\x83\x8D\x81\x5B\x83\x68</a>\n</div>\n");
+    }
+
+    html.extend_from_slice(b"</body></html>\n");
+}
+
+/// Writes the first post and the last few posts of a dat.
+fn write_digest(board: &BoardRef, key: u64, dat: &[u8], html: &mut Vec<u8>) {
+    let settings = board.settings();
+    let contents = settings.get::<ContentsNumber>().cloned()
+        .unwrap_or(DEFAULT_CONTENTS_NUMBER) as usize;
+    let line_number = settings.get::<LineNumber>().cloned();
+
+    // Reading from a slice never fails.
+    let posts: Vec<Option<Record>> = dat::Reader::new(dat)
+        .map(|r| r.unwrap().ok())
+        .collect();
+    let tail = cmp::max(1, posts.len().saturating_sub(contents.saturating_sub(1)));
+
+    // Shown in place of a line that cannot be parsed, as 2channel does.
+    let broken = Record {
+        name: BROKEN.to_vec(),
+        datetime: BROKEN.to_vec(),
+        body: BROKEN.to_vec(),
+        ..Record::default()
+    };
+
+    let first = posts.iter().enumerate().take(1);
+    for (i, post) in first.chain(posts.iter().enumerate().skip(tail)) {
+        let post = post.as_ref().unwrap_or(&broken);
+        let (name, mail, body) = (&post.name[..], &post.mail[..], &post.body[..]);
+
+        let n = i + 1;
+        write!(html, "<dt>{} ", n).unwrap();
+        html.extend_from_slice(COLON);
+        if mail.is_empty() {
+            html.extend_from_slice(b"<font color=\"green\"><b>");
+            html.extend_from_slice(name);
+            html.extend_from_slice(b"</b></font>");
+        } else {
+            html.extend_from_slice(b"<a href=\"mailto:");
+            html.extend_from_slice(mail);
+            html.extend_from_slice(b"\"><b>");
+            html.extend_from_slice(name);
+            html.extend_from_slice(b"</b></a>");
+        }
+        html.extend_from_slice(COLON);
+        html.extend_from_slice(&post.datetime_with_id());
+        html.extend_from_slice(b"<dd>");
+
+        match line_number.and_then(|max| truncation_point(body, max as usize)) {
+            Some(end) => {
+                html.extend_from_slice(&body[..end]);
+                // "（省略されました・・全てを読むには"
+                html.extend_from_slice(b"<br><font color=\"green\">\x81\x69\
+                    \x8F\xC8\x97\xAA\x82\xB3\x82\xEA\x82\xDC\x82\xB5\x82\xBD\x81\x45\x81\x45\
+                    \x91\x53\x82\xC4\x82\xF0\x93\xC7\x82\xDE\x82\xC9\x82\xCD");
+                write!(html, "<a href=\"../test/read.cgi/{}/{}/{}\">", board.id(), key, n)
+                    .unwrap();
+                // "ここ</a>を押してください）"
+                html.extend_from_slice(b"\x82\xB1\x82\xB1</a>\
+                    \x82\xF0\x89\x9F\x82\xB5\x82\xC4\x82\xAD\x82\xBE\x82\xB3\x82\xA2\x81\x6A\
+                    </font>");
+            },
+            None => html.extend_from_slice(body),
+        }
+        html.extend_from_slice(b"<br><br>\n");
+    }
+}
+
+/// Returns the position of the `max`-th `<br>` of `body` if `body` has
+/// more than `max` lines.
+fn truncation_point(body: &[u8], max: usize) -> Option<usize> {
+    let mut lines = 1;
+    let mut i = 0;
+    while let Some(j) = memchr(b'<', &body[i..]) {
+        if body[(i+j)..].starts_with(b"<br>") {
+            if lines == max {
+                return Some(i + j);
+            }
+            lines += 1;
+        }
+        i += j + 1;
+    }
+    None
+}
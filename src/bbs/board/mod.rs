@@ -1,12 +1,17 @@
+pub(in bbs) mod index_html;
+pub(in bbs) mod subject_json;
 mod topics;
 
 pub(in bbs) use self::topics::{Topics, TopicsBuilder};
 
 use std::borrow::Borrow;
+use std::cmp;
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use rocket::http::uncased::{Uncased, UncasedStr};
 
 use super::Topic;
@@ -16,8 +21,14 @@ use util::LinkedHashMap;
 
 pub struct Board {
     id: Box<UncasedStr>,
-    settings: Settings,
+    /// The current settings and the number of times they have been
+    /// replaced.
+    settings: RwLock<(Arc<Settings>, u64)>,
+    /// When SETTING.TXT has been checked for changes for the last time.
+    pub(in bbs) settings_checked: Mutex<Instant>,
     pub(in bbs) topics: RwLock<Topics>,
+    /// The number of `BoardRef`s to the board.
+    refs: AtomicUsize,
 }
 
 pub struct BoardBuilder {
@@ -27,6 +38,9 @@ pub struct BoardBuilder {
 }
 
 pub type SubjectTxt = Cacheable<Vec<u8>>;
+pub type Dat = Cacheable<Vec<u8>>;
+pub type IndexHtml = Cacheable<Vec<u8>>;
+pub type SubjectJson = Cacheable<Vec<u8>>;
 
 type TopicMap = LinkedHashMap<u64, Topic>;
 
@@ -39,29 +53,84 @@ impl Board {
         self.id.as_str()
     }
 
-    pub fn settings(&self) -> &Settings {
-        &self.settings
+    /// Returns the current settings of the board. The returned settings are
+    /// not affected by later reloads.
+    pub fn settings(&self) -> Arc<Settings> {
+        Arc::clone(&self.settings.read().0)
+    }
+
+    /// Returns the current settings along with their generation, which
+    /// tells whether they have been replaced since.
+    pub(in bbs) fn settings_with_generation(&self) -> (Arc<Settings>, u64) {
+        let current = self.settings.read();
+        (Arc::clone(&current.0), current.1)
+    }
+
+    /// Replaces the settings of the board, invalidating index.html which
+    /// depends on them.
+    pub(in bbs) fn replace_settings(&self, settings: Settings) {
+        let generation = {
+            let mut current = self.settings.write();
+            current.0 = Arc::new(settings);
+            current.1 += 1;
+            current.1
+        };
+        self.topics.write().reset_index_html(generation);
     }
 
     pub fn subject_txt(&self) -> Arc<SubjectTxt> {
         Arc::clone(self.topics.read().subject_txt())
     }
 
+    pub(in bbs) fn acquire(&self) {
+        self.refs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(in bbs) fn release(&self) {
+        self.refs.fetch_sub(1, Ordering::Release);
+    }
+
+    /// Returns whether no `BoardRef` refers to the board. Once a removed
+    /// board is unreferenced, it stays so.
+    pub(in bbs) fn is_unreferenced(&self) -> bool {
+        0 == self.refs.load(Ordering::Acquire)
+    }
+
     fn new(id: Box<UncasedStr>, settings: Settings, topics: Topics) -> Self {
         Board {
             id,
-            settings,
+            settings: RwLock::new((Arc::new(settings), 0)),
+            settings_checked: Mutex::new(Instant::now()),
             topics: RwLock::new(topics),
+            refs: AtomicUsize::new(0),
         }
     }
 }
 
+/// Returns the path of an archived dat relative to the board directory,
+/// laid out like 2channel's, e.g. `kako/1234/12345/1234567890.dat`
+/// (or `kako/123/123456789.dat` for keys shorter than 10 digits).
+pub fn kako_path(key: u64) -> String {
+    let k = key.to_string();
+    if k.len() >= 10 {
+        format!("kako/{}/{}/{}.dat", &k[..4], &k[..5], k)
+    } else {
+        format!("kako/{}/{}.dat", &k[..cmp::min(3, k.len())], k)
+    }
+}
+
 impl Borrow<UncasedStr> for Board {
     fn borrow(&self) -> &UncasedStr {
         &self.id
     }
 }
 
+impl Borrow<UncasedStr> for Box<Board> {
+    fn borrow(&self) -> &UncasedStr {
+        &self.id
+    }
+}
+
 /// The hash only depends on the board id.
 impl Hash for Board {
     fn hash<H: Hasher>(&self, state: &mut H) {
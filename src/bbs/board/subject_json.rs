@@ -0,0 +1,62 @@
+//! The topic list of a board in JSON, the counterpart of subject.txt for
+//! the JSON API.
+
+use std::fs;
+use std::time::UNIX_EPOCH;
+
+use serde_json;
+
+use super::Topics;
+use bbs::BoardRef;
+use encoding::from_shift_jis;
+
+#[derive(Serialize)]
+struct Subject<'a> {
+    board: &'a str,
+    topics: &'a [Entry],
+}
+
+/// The topic list copied out of `Topics` so that the dats can be examined
+/// without holding the lock.
+pub(in bbs) struct Snapshot {
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    key: u64,
+    title: String,
+    post_count: usize,
+    /// The mtime of the dat in seconds since the UNIX epoch.
+    last_modified: Option<u64>,
+    stopped: bool,
+}
+
+impl Snapshot {
+    pub(in bbs) fn new(topics: &Topics) -> Self {
+        let entries = topics.iter()
+            .map(|(key, t)| Entry {
+                key,
+                title: from_shift_jis(t.title()).into_owned(),
+                post_count: t.post_count(),
+                last_modified: None,
+                stopped: t.is_stopped(),
+            })
+            .collect();
+        Snapshot { entries }
+    }
+}
+
+pub(in bbs) fn make(board: &BoardRef, mut snapshot: Snapshot, json: &mut Vec<u8>) {
+    for entry in &mut snapshot.entries {
+        entry.last_modified = fs::metadata(board.dat_path(entry.key))
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+    }
+
+    json.clear();
+    serde_json::to_writer(json, &Subject { board: board.id(), topics: &snapshot.entries })
+        .expect("failed to serialize subject.json");
+}
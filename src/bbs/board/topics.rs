@@ -1,12 +1,16 @@
 use std::any::TypeId;
+use std::cmp;
 use std::hash::{Hash, Hasher};
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::mem;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use lazy_init::LazyTransform;
 
-use super::{TopicMap, SubjectTxt};
+use super::{IndexHtml, SubjectJson, TopicMap, SubjectTxt};
 use bbs::Topic;
 use responder::{Cacheable, Metadata};
 use util::LinkedHashMap;
@@ -14,6 +18,20 @@ use util::LinkedHashMap;
 pub struct Topics {
     map: TopicMap,
     subject_txt: LazyTransform<SubjectTxt, Arc<SubjectTxt>>,
+    index_html: LazyTransform<IndexHtml, Arc<IndexHtml>>,
+    subject_json: LazyTransform<SubjectJson, Arc<SubjectJson>>,
+    /// Incremented whenever the caches are reset.
+    generation: u64,
+    /// The generation of the settings of the board that index.html is to
+    /// be made from, see `Board::settings_with_generation`.
+    settings_generation: u64,
+    /// Whether the order of the topics has changed since subject.txt was
+    /// persisted.
+    reordered: bool,
+    persisted: Option<Instant>,
+    /// Set when the board has been removed, after which no topic is found
+    /// so that nothing is written to the directory of the board.
+    closed: bool,
 }
 
 pub struct TopicsBuilder {
@@ -21,6 +39,8 @@ pub struct TopicsBuilder {
 }
 
 struct Id;
+struct IndexId;
+struct JsonId;
 
 // "TTTTTTTTTT.dat<>TITLE (NNNN)\n"
 // len = 24 + len(TITLE)
@@ -32,13 +52,27 @@ impl Topics {
     }
 
     pub fn get(&self, key: u64) -> Option<&Topic> {
+        if self.closed {
+            return None;
+        }
         self.map.get(key)
     }
 
     pub fn get_mut(&mut self, key: u64) -> Option<&mut Topic> {
+        if self.closed {
+            return None;
+        }
         self.map.get_mut(key)
     }
 
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn set_closed(&mut self, closed: bool) {
+        self.closed = closed;
+    }
+
     pub fn contains_key(&mut self, key: u64) -> bool {
         self.map.contains_key(key)
     }
@@ -51,6 +85,19 @@ impl Topics {
         ret
     }
 
+    /// Moves the topic to the top of subject.txt.
+    pub fn bump(&mut self, key: u64) -> bool {
+        if self.map.iter().next().map(|(k, _)| k) == Some(key) {
+            return true;
+        }
+        let ret = self.map.bump(key);
+        if ret {
+            self.reordered = true;
+            self.reset_txt(false);
+        }
+        ret
+    }
+
     pub fn remove(&mut self, key: u64) -> Option<Topic> {
         let ret = self.map.remove(key);
         if ret.is_some() { self.reset_txt(false); }
@@ -64,43 +111,130 @@ impl Topics {
         })
     }
 
+    /// Returns the cached index.html of the board unless it has been reset.
+    pub fn cached_index_html(&self) -> Option<&Arc<IndexHtml>> {
+        self.index_html.get()
+    }
+
+    /// Caches `body` as index.html if nothing has been reset since
+    /// `generation` and it has been made from the current settings, or
+    /// returns it uncached otherwise so that an outdated page is never
+    /// cached.
+    pub fn cache_index_html(&self, generation: u64, settings_generation: u64, body: Vec<u8>)
+        -> Arc<IndexHtml>
+    {
+        let current = self.generation == generation
+            && self.settings_generation == settings_generation;
+        cache(&self.index_html, current, body, IndexId::in_u64())
+    }
+
+    /// Returns the cached subject.json of the board unless it has been reset.
+    pub fn cached_subject_json(&self) -> Option<&Arc<SubjectJson>> {
+        self.subject_json.get()
+    }
+
+    /// Caches `body` as subject.json in the same way as `cache_index_html`.
+    pub fn cache_subject_json(&self, generation: u64, body: Vec<u8>) -> Arc<SubjectJson> {
+        cache(&self.subject_json, self.generation == generation, body, JsonId::in_u64())
+    }
+
+    /// Returns the number of times the caches have been reset, which tells
+    /// whether the topics have changed since a snapshot was taken.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the topic that has not been created or bumped for the longest
+    /// time.
+    pub fn back(&self) -> Option<&Topic> {
+        self.map.back().map(|(_, t)| t)
+    }
+
+    /// Returns an iterator over the topics in the order of subject.txt.
+    pub fn iter(&self) -> impl Iterator<Item=(u64, &Topic)> {
+        self.map.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Atomically writes subject.txt to `path`, which is read at startup
+    /// to restore the order of the topics.
+    pub fn persist(&mut self, path: &Path) -> io::Result<()> {
+        write_txt(path, self.subject_txt().body())?;
+        self.reordered = false;
+        self.persisted = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Atomically writes subject.txt without the topic `key` to `path`, so
+    /// that the topic is removed only once that has succeeded.
+    pub fn persist_without(&mut self, path: &Path, key: u64) -> io::Result<()> {
+        let mut txt = Vec::new();
+        self.write_lines(&mut txt, Some(key));
+        write_txt(path, &txt)?;
+        self.reordered = false;
+        self.persisted = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Persists subject.txt if the order of the topics has changed and it
+    /// has not been persisted for `interval`. Returns whether it has been
+    /// written.
+    ///
+    /// New topics need not be persisted since topics missing from
+    /// subject.txt are placed on the top in the order of their keys.
+    pub fn persist_order(&mut self, path: &Path, interval: Duration) -> io::Result<bool> {
+        if ! self.reordered || self.persisted.map_or(false, |t| t.elapsed() < interval) {
+            return Ok(false);
+        }
+        self.persist(path).map(|()| true)
+    }
+
+    /// Invalidates the caches of subject.txt, index.html and subject.json.
     pub fn reset_txt(&mut self, addition: bool) {
-        // Try to reuse the buffer:
-        let dummy = LazyTransform::default();
-        let txt = mem::replace(&mut self.subject_txt, dummy)
-            .into_inner()
-            .map(|arc| match Arc::try_unwrap(arc) {
-                Ok(txt) => txt,
-                Err(arc) => { // create new buffer
-                    let cap = arc.body().len() +
-                        if addition { STANDARD_LINE_LEN } else { 0 };
-                    let buf = Vec::with_capacity(cap);
-                    Cacheable::new(buf, Metadata::now(Id::in_u64()))
-                },
-            })
-            .unwrap_or_else(|txt| txt);
-        mem::replace(&mut self.subject_txt, LazyTransform::new(txt));
+        let extra = if addition { STANDARD_LINE_LEN } else { 0 };
+        self.generation += 1;
+        reset(&mut self.subject_txt, extra, Id::in_u64());
+        reset(&mut self.index_html, 0, IndexId::in_u64());
+        reset(&mut self.subject_json, 0, JsonId::in_u64());
+    }
+
+    /// Invalidates the cache of index.html only when the settings of the
+    /// board have been replaced, so that it is only cached again when made
+    /// from the settings of `settings_generation` or later.
+    pub fn reset_index_html(&mut self, settings_generation: u64) {
+        self.generation += 1;
+        // Concurrent replacements may reset in any order.
+        self.settings_generation = cmp::max(self.settings_generation, settings_generation);
+        reset(&mut self.index_html, 0, IndexId::in_u64());
     }
 
     fn make_txt(&self, txt: &mut SubjectTxt) {
         {
-            let mut vec = txt.body_mut();
-
+            let vec = txt.body_mut();
             vec.clear();
-
-            for (k, t) in &self.map {
-                // "TTTTTTTTTT.dat<>TITLE (NNNN)\n"
-                write!(&mut vec, "{}", k).unwrap();
-                vec.extend_from_slice(b".dat<>");
-                vec.extend_from_slice(t.title());
-                vec.extend_from_slice(b" (");
-                write!(&mut vec, "{}", t.post_count()).unwrap();
-                vec.extend_from_slice(b")\n");
-            }
+            self.write_lines(vec, None);
         }
 
         txt.modify(Id::in_u64());
     }
+
+    fn write_lines(&self, vec: &mut Vec<u8>, skip: Option<u64>) {
+        for (k, t) in &self.map {
+            if Some(k) == skip {
+                continue;
+            }
+            // "TTTTTTTTTT.dat<>TITLE (NNNN)\n"
+            write!(vec, "{}", k).unwrap();
+            vec.extend_from_slice(b".dat<>");
+            vec.extend_from_slice(t.title());
+            vec.extend_from_slice(b" (");
+            write!(vec, "{}", t.post_count()).unwrap();
+            vec.extend_from_slice(b")\n");
+        }
+    }
 }
 
 impl TopicsBuilder {
@@ -119,26 +253,95 @@ impl TopicsBuilder {
         Topics {
             map: self.map,
             subject_txt: LazyTransform::new(SubjectTxt::default()),
+            index_html: LazyTransform::new(IndexHtml::default()),
+            subject_json: LazyTransform::new(SubjectJson::default()),
+            generation: 0,
+            settings_generation: 0,
+            reordered: false,
+            persisted: None,
+            closed: false,
         }
     }
 }
 
 impl Id {
     fn in_u64() -> u64 {
-        struct IdentityHasher(u64);
-        impl Hasher for IdentityHasher {
-            fn finish(&self) -> u64 { self.0 }
-            fn write_u64(&mut self, n: u64) { self.0 = n; }
-            // Not likely to be called:
-            fn write(&mut self, bytes: &[u8]) {
-                for &b in bytes {
-                    self.0 = (self.0 << 8) | b as u64;
-                }
+        type_id_in_u64::<Self>()
+    }
+}
+
+impl IndexId {
+    fn in_u64() -> u64 {
+        type_id_in_u64::<Self>()
+    }
+}
+
+impl JsonId {
+    fn in_u64() -> u64 {
+        type_id_in_u64::<Self>()
+    }
+}
+
+fn reset(
+    lazy: &mut LazyTransform<Cacheable<Vec<u8>>, Arc<Cacheable<Vec<u8>>>>,
+    extra: usize,
+    id: u64,
+) {
+    // Try to reuse the buffer:
+    let dummy = LazyTransform::default();
+    let cached = mem::replace(lazy, dummy)
+        .into_inner()
+        .map(|arc| match Arc::try_unwrap(arc) {
+            Ok(cached) => cached,
+            Err(arc) => { // create new buffer
+                let buf = Vec::with_capacity(arc.body().len() + extra);
+                Cacheable::new(buf, Metadata::now(id))
+            },
+        })
+        .unwrap_or_else(|cached| cached);
+    mem::replace(lazy, LazyTransform::new(cached));
+}
+
+fn cache(
+    lazy: &LazyTransform<Cacheable<Vec<u8>>, Arc<Cacheable<Vec<u8>>>>,
+    current: bool,
+    body: Vec<u8>,
+    id: u64,
+) -> Arc<Cacheable<Vec<u8>>> {
+    if ! current {
+        return Arc::new(Cacheable::new(body, Metadata::now(id)));
+    }
+    Arc::clone(lazy.get_or_create(|mut cached| {
+        *cached.body_mut() = body;
+        cached.modify(id);
+        Arc::new(cached)
+    }))
+}
+
+fn write_txt(path: &Path, body: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("txt.tmp");
+    {
+        let mut f = File::create(&tmp)?;
+        f.write_all(body)?;
+        f.sync_data()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+fn type_id_in_u64<T: 'static>() -> u64 {
+    struct IdentityHasher(u64);
+    impl Hasher for IdentityHasher {
+        fn finish(&self) -> u64 { self.0 }
+        fn write_u64(&mut self, n: u64) { self.0 = n; }
+        // Not likely to be called:
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = (self.0 << 8) | b as u64;
             }
         }
-
-        let mut h = IdentityHasher(0);
-        TypeId::of::<Self>().hash(&mut h);
-        h.finish()
     }
+
+    let mut h = IdentityHasher(0);
+    TypeId::of::<T>().hash(&mut h);
+    h.finish()
 }
@@ -0,0 +1,347 @@
+//! Reading and writing dat lines.
+//!
+//! A dat line has the form
+//!
+//! ```text
+//! name<>mail<>datetime ID:xxxxxxxxx<> body <>title\n
+//! ```
+//!
+//! where the title is only present on the first line. `<` never appears
+//! inside a field since it is always escaped, so a line with other than five
+//! fields is malformed.
+//!
+//! `Reader` parses the lines written by `write_line` back into the same
+//! `Record`s, and `Record::write_to` writes them back byte for byte. Bodies
+//! lacking the surrounding spaces, as found in some dats, are tolerated and
+//! written back without them.
+
+use std::io::{self, BufRead, Write};
+
+/// A post parsed from a dat line.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Record {
+    pub name: Vec<u8>,
+    pub mail: Vec<u8>,
+    /// The datetime field without the ID.
+    pub datetime: Vec<u8>,
+    /// The poster ID without the leading `ID:`, which is split out of the
+    /// datetime field if it is the last word of the field.
+    pub id: Option<Vec<u8>>,
+    /// The body without the surrounding spaces.
+    pub body: Vec<u8>,
+    /// Whether the body lacks the surrounding spaces in the dat.
+    pub unspaced: bool,
+    /// The title, which is empty except on the first line.
+    pub title: Vec<u8>,
+}
+
+/// A line that could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Malformed {
+    /// The 1-based line number.
+    pub number: usize,
+    /// The byte offset of the start of the line in the dat.
+    pub offset: u64,
+    /// The line without the trailing newline.
+    pub line: Vec<u8>,
+}
+
+/// An iterator over the lines of a dat.
+///
+/// A line lacking the trailing newline, i.e. a partially written one, is
+/// reported as malformed.
+pub struct Reader<R> {
+    inner: R,
+    number: usize,
+    offset: u64,
+}
+
+/// An iterator over the lines of a body delimited by `<br>`, without the
+/// spaces that 2channel puts around `<br>`.
+pub struct BodyLines<'a>(Option<&'a [u8]>);
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Reader {
+            inner,
+            number: 0,
+            offset: 0,
+        }
+    }
+
+    /// Returns the number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.number
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = io::Result<Result<Record, Malformed>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+        let len = match self.inner.read_until(b'\n', &mut line) {
+            Ok(0) => return None,
+            Ok(len) => len,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let offset = self.offset;
+        self.number += 1;
+        self.offset += len as u64;
+
+        let complete = Some(&b'\n') == line.last();
+        if complete {
+            line.pop();
+        }
+        let record = if complete { parse_line(&line) } else { None };
+        Some(Ok(record.ok_or_else(|| Malformed { number: self.number, offset, line })))
+    }
+}
+
+/// Parses a dat line without the trailing newline.
+pub fn parse_line(line: &[u8]) -> Option<Record> {
+    let mut fields = [&[][..]; 5];
+    let mut rest = line;
+    for (i, field) in fields.iter_mut().enumerate() {
+        match find_delimiter(rest) {
+            Some(j) if i < 4 => {
+                *field = &rest[..j];
+                rest = &rest[(j+2)..];
+            },
+            None if i == 4 => *field = rest,
+            _ => return None,
+        }
+    }
+    let [name, mail, datetime, body, title] = fields;
+
+    let (datetime, id) = split_id(datetime);
+    let (body, unspaced) = if body.len() >= 2 && body.starts_with(b" ") && body.ends_with(b" ") {
+        (&body[1..(body.len()-1)], false)
+    } else {
+        (body, true)
+    };
+
+    Some(Record {
+        name: name.to_owned(),
+        mail: mail.to_owned(),
+        datetime: datetime.to_owned(),
+        id: id.map(ToOwned::to_owned),
+        body: body.to_owned(),
+        unspaced,
+        title: title.to_owned(),
+    })
+}
+
+/// Writes a dat line. `datetime` includes the ID if any.
+pub fn write_line<W: Write>(
+    mut w: W, name: &[u8], mail: &[u8], datetime: &[u8], body: &[u8], title: &[u8],
+) -> io::Result<()>
+{
+    // A single `write_all` makes it less likely for concurrent readers to
+    // see a partial line.
+    w.write_all(&make_line(name, mail, datetime, body, title))
+}
+
+/// Makes a dat line, which is appended to a dat at once with
+/// `DatRef::append`.
+///
+/// The fields must already be in the form of dat fields, i.e. without
+/// newlines or `<>`.
+pub fn make_line(name: &[u8], mail: &[u8], datetime: &[u8], body: &[u8], title: &[u8])
+    -> Vec<u8>
+{
+    make(name, mail, datetime, body, title, true)
+}
+
+fn make(name: &[u8], mail: &[u8], datetime: &[u8], body: &[u8], title: &[u8], spaced: bool)
+    -> Vec<u8>
+{
+    debug_assert!(
+        [name, mail, datetime, body, title].iter().all(|f| is_field(f)),
+        "a dat field with a newline or `<>`"
+    );
+    // name<>mail<>datetime<> body <>title\n
+    let mut line = Vec::with_capacity(
+        name.len() + mail.len() + datetime.len() + body.len() + title.len() + 11
+    );
+    line.extend_from_slice(name);
+    line.extend_from_slice(b"<>");
+    line.extend_from_slice(mail);
+    line.extend_from_slice(b"<>");
+    line.extend_from_slice(datetime);
+    line.extend_from_slice(b"<>");
+    if spaced { line.push(b' '); }
+    line.extend_from_slice(body);
+    if spaced { line.push(b' '); }
+    line.extend_from_slice(b"<>");
+    line.extend_from_slice(title);
+    line.push(b'\n');
+    line
+}
+
+fn is_field(f: &[u8]) -> bool {
+    ! f.contains(&b'\n') && ! f.windows(2).any(|w| w == b"<>")
+}
+
+impl Record {
+    /// Returns the datetime field including the ID, as written in the dat.
+    pub fn datetime_with_id(&self) -> Vec<u8> {
+        let mut ret = self.datetime.clone();
+        if let Some(ref id) = self.id {
+            if ! ret.is_empty() {
+                ret.push(b' ');
+            }
+            ret.extend_from_slice(b"ID:");
+            ret.extend_from_slice(id);
+        }
+        ret
+    }
+
+    pub fn body_lines(&self) -> BodyLines {
+        BodyLines(Some(&self.body))
+    }
+
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let datetime = self.datetime_with_id();
+        let spaced = ! self.unspaced;
+        w.write_all(&make(&self.name, &self.mail, &datetime, &self.body, &self.title, spaced))
+    }
+}
+
+impl<'a> Iterator for BodyLines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let s = self.0?;
+        match s.windows(4).position(|w| w == b"<br>") {
+            Some(i) => {
+                let mut line = &s[..i];
+                if line.ends_with(b" ") {
+                    line = &line[..(line.len()-1)];
+                }
+                let mut rest = &s[(i+4)..];
+                if rest.starts_with(b" ") {
+                    rest = &rest[1..];
+                }
+                self.0 = Some(rest);
+                Some(line)
+            },
+            None => {
+                self.0 = None;
+                Some(s)
+            },
+        }
+    }
+}
+
+fn find_delimiter(s: &[u8]) -> Option<usize> {
+    s.windows(2).position(|w| w == b"<>")
+}
+
+/// Splits a trailing `ID:xxx` off a datetime field.
+fn split_id(datetime: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let (prefix, last) = match datetime.iter().rposition(|&c| b' ' == c) {
+        Some(i) => (&datetime[..i], &datetime[(i+1)..]),
+        None => (&datetime[..0], datetime),
+    };
+    // " ID:xxx" would not round trip.
+    let splittable = ! prefix.is_empty() || last.len() == datetime.len();
+    if splittable && last.starts_with(b"ID:") && last.len() > 3 {
+        (prefix, Some(&last[3..]))
+    } else {
+        (datetime, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAT: &[u8] = b"\
+        maji<>sage<>2012/10/28(Sun) 13:12:07.97 ID:nXycV/Aa0<> ('A') <br> ... <>('A')\n\
+        <><>ID:???<> a <>\n\
+        broken<>line\n\
+        maji<>sage<>2012/10/28(Sun) 15:15:21.32<>  <>\n\
+        torn<>";
+
+    #[test]
+    fn read() {
+        let mut reader = Reader::new(DAT);
+
+        let r = reader.next().unwrap().unwrap().unwrap();
+        assert_eq!(b"maji", &*r.name);
+        assert_eq!(b"2012/10/28(Sun) 13:12:07.97", &*r.datetime);
+        assert_eq!(Some(&b"nXycV/Aa0"[..]), r.id.as_ref().map(|id| &**id));
+        assert_eq!(vec![&b"('A')"[..], b"..."], r.body_lines().collect::<Vec<_>>());
+        assert_eq!(b"('A')", &*r.title);
+
+        let r = reader.next().unwrap().unwrap().unwrap();
+        assert_eq!(b"", &*r.datetime);
+        assert_eq!(Some(&b"???"[..]), r.id.as_ref().map(|id| &**id));
+
+        let m = reader.next().unwrap().unwrap().unwrap_err();
+        assert_eq!(3, m.number);
+        assert_eq!(b"broken<>line", &*m.line);
+
+        let r = reader.next().unwrap().unwrap().unwrap();
+        assert_eq!(None, r.id);
+        assert_eq!(b"", &*r.body);
+
+        let m = reader.next().unwrap().unwrap().unwrap_err();
+        assert_eq!(5, m.number);
+        assert_eq!((DAT.len() - 6) as u64, m.offset);
+
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut written = Vec::new();
+        for r in Reader::new(DAT) {
+            if let Ok(r) = r.unwrap() {
+                r.write_to(&mut written).unwrap();
+            }
+        }
+        let expected: Vec<u8> = DAT.split(|&c| b'\n' == c)
+            .filter(|l| parse_line(l).is_some())
+            .flat_map(|l| l.iter().chain(b"\n"))
+            .cloned()
+            .collect();
+        assert_eq!(expected, written);
+    }
+
+    #[test]
+    fn unspaced_body() {
+        const LINES: &[&[u8]] = &[
+            b"maji<>sage<>2012/10/28(Sun) 13:12:07.97<>('A') ...<>('A')",
+            b"a<>b<>c<> d<>",
+            b"a<>b<>c<> <>",
+        ];
+        for &line in LINES {
+            let r = parse_line(line).unwrap();
+            assert!(r.unspaced);
+            let mut written = Vec::new();
+            r.write_to(&mut written).unwrap();
+            assert_eq!(line, &written[..(written.len()-1)]);
+        }
+        assert_eq!(b"('A') ...", &*parse_line(LINES[0]).unwrap().body);
+        assert!(! parse_line(b"a<>b<>c<> d <>").unwrap().unspaced);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn raw_newline() {
+        make_line(b"name", b"", b"", b"a\nb", b"");
+    }
+}
@@ -1,43 +1,71 @@
 pub mod board;
+pub mod dat;
 pub mod topic;
 
 pub use self::board::Board;
 pub use self::topic::Topic;
 
-use std::borrow::Cow;
-use std::collections::HashSet;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fs::{self, File};
-use std::io;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::ops::{Deref, DerefMut};
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
-use std::result;
 use std::str;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use owning_ref::{OwningRef, OwningRefMut};
-use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
+use memchr;
+use owning_ref::OwningRef;
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use rocket::http::uncased::UncasedStr;
 use rocket::request::{FromRequest, Outcome, Request, State};
+use typemap::ShareMap;
 
-use self::board::{SubjectTxt, Topics};
+use self::board::{Dat, IndexHtml, SubjectJson, SubjectTxt, Topics};
 use middleware::{self, BeforeMiddleware, AfterMiddleware, Middlewares};
+use responder::{Cacheable, Metadata};
 use post::Post;
-use setting::Settings;
+use setting::{Setting, Settings};
+use setting::common;
+use util::erase_lifetime;
 use validator;
 
+pub const DEFAULT_MAX_RES: u32 = 1000;
+pub const DEFAULT_DAT_MAX_KB: u32 = 512;
+pub const DEFAULT_SETTINGS_POLL_SECS: u64 = 5;
+/// The minimum interval between writes of subject.txt caused by posts.
+pub const PERSIST_INTERVAL_SECS: u64 = 10;
+
 pub struct Bbs {
-    boards: HashSet<Board>,
+    boards: RwLock<HashSet<Box<Board>>>,
+    /// Boards removed at runtime, which are kept alive while `BoardRef`s
+    /// refer to them since those borrow them for the lifetime of the `Bbs`.
+    removed: Mutex<Vec<Box<Board>>>,
     middlewares: Middlewares,
     workspace: Box<Path>,
+    sync_policy: SyncPolicy,
+    settings_poll: Option<Duration>,
+}
+
+/// When appends to dats are flushed to the disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave it to the OS, which may lose the last posts on a power failure.
+    Never,
+    /// Sync the data of the dat after every append. This is the default.
+    Always,
 }
 
-#[derive(Clone, Copy)]
+/// A board with a snapshot of its settings, which are kept for the whole
+/// request even if SETTING.TXT is reloaded meanwhile.
 pub struct BoardRef<'a> {
     inner: &'a Board,
     bbs: &'a Bbs,
+    settings: Arc<Settings>,
+    settings_generation: u64,
 }
 
 pub struct TopicRef<'a> {
@@ -45,8 +73,11 @@ pub struct TopicRef<'a> {
     board: &'a BoardRef<'a>,
 }
 
+/// A topic locked for writing, which holds the lock of all the topics of
+/// the board so that subject.txt can be updated along with the topic.
 pub struct TopicMut<'a> {
-    inner: OwningRefMut<RwLockWriteGuard<'a, Topics>, Topic>,
+    topics: RwLockWriteGuard<'a, Topics>,
+    key: u64,
     board: &'a BoardRef<'a>,
 }
 
@@ -70,55 +101,224 @@ impl Bbs {
         let mut boards = HashSet::new();
         for brd_ent in fs::read_dir(workspace)? {
             let brd_ent = brd_ent?;
-            let mut path = brd_ent.path();
+            let path = brd_ent.path();
             if ! path.is_dir() { continue; }
 
-            const MISSING_FNAME: &str =
-                "`DirEntry.path().file_name()` returned a `None`";
             let board_id = {
                 let n = path.file_name().expect(MISSING_FNAME).as_bytes();
-                unsafe {
-                    if ! n.iter().all(|&c| validator::is_alphanum(c)) {
-                        continue;
-                    }
-                    String::from_utf8_unchecked(n.to_owned())
+                if ! is_board_id(n) {
+                    continue;
                 }
+                unsafe { String::from_utf8_unchecked(n.to_owned()) }
             };
 
-            path.push("SETTING.TXT");
-            let settings = if path.exists() {
-                Settings::load(&File::open(&path)?)?
-            } else {
-                Settings::empty()
-            };
-
-            path.set_file_name("dat");
-            fs::create_dir_all(&path)?;
-            let mut builder = Board::build(board_id, settings);
-            for ent in fs::read_dir(&path)? {
-                let ent = ent?;
-                let p = ent.path();
-                if ! p.is_file() { continue; }
-                let n = p.file_name().expect(MISSING_FNAME).as_bytes();
-                if ! n.ends_with(b".dat") { continue; }
-                let key = match ::atoi::atoi(&n[0..(n.len()-4)]) {
-                    Some(::checked::Checked(Some(key))) => key,
-                    _ => continue,
-                };
-                let topic = Topic::load(key, File::open(&p)?)?;
-                builder.topic(key, topic);
-            }
-
-            boards.insert(builder.finish());
+            boards.insert(Box::new(load_board(path, board_id)?));
         }
 
         Ok(Bbs {
-            boards,
+            boards: RwLock::new(boards),
+            removed: Mutex::new(Vec::new()),
             middlewares: Middlewares::new(),
             workspace: workspace.to_owned().into_boxed_path(),
+            sync_policy: SyncPolicy::Always,
+            settings_poll: Some(Duration::from_secs(DEFAULT_SETTINGS_POLL_SECS)),
         })
     }
 
+    /// Creates the board `id` with `setting_txt` as its SETTING.TXT, laid out
+    /// in the workspace as the boards loaded at startup.
+    ///
+    /// Fails with `InvalidInput` if `id` is not alphanumeric, and with
+    /// `AlreadyExists` if the board or its directory already exists.
+    pub fn create_board(&self, id: &str, setting_txt: &[u8]) -> io::Result<()> {
+        if ! is_board_id(id.as_bytes()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid board id"));
+        }
+
+        let exists = || io::Error::new(io::ErrorKind::AlreadyExists, "board already exists");
+        if self.boards.read().contains(UncasedStr::new(id)) {
+            return Err(exists());
+        }
+
+        // Making the directory first claims the id against concurrent
+        // creations, so the boards need not be locked during the I/O.
+        let path = self.workspace.join(id);
+        fs::create_dir(&path)?;
+        let board = fs::create_dir(path.join("dat"))
+            .and_then(|()| write_atomically(&path.join("SETTING.TXT"), setting_txt))
+            .and_then(|()| load_board(path.clone(), id.to_owned()));
+        let inserted = board.and_then(|board| {
+            let mut boards = self.boards.write();
+            // Another board may differ only in case on a case-sensitive
+            // file system.
+            if boards.contains(UncasedStr::new(id)) {
+                return Err(exists());
+            }
+            boards.insert(Box::new(board));
+            Ok(())
+        });
+        if inserted.is_err() {
+            if let Err(e) = fs::remove_dir_all(&path) {
+                warn!("failed to clean up {:?}: {:?}", &path, e);
+            }
+        } else {
+            info!("created a board, {}", id);
+        }
+        inserted
+    }
+
+    /// Makes the board `id` read-only by setting `BBS_HEISA` in its
+    /// SETTING.TXT, which is kept across restarts. Returns `Ok(false)` if
+    /// there is no such board.
+    pub fn retire_board(&self, id: &str) -> io::Result<bool> {
+        let boards = self.boards.read();
+        let board = match boards.get(UncasedStr::new(id)) {
+            Some(board) => board,
+            None => return Ok(false),
+        };
+
+        let settings = board.settings();
+        let key = common::Heisa::KEY.as_bytes();
+        let mut txt = Vec::new();
+        for line in (*settings).as_ref().split(|&c| b'\n' == c) {
+            let is_heisa = line.starts_with(key) && Some(&b'=') == line.get(key.len());
+            if ! line.is_empty() && ! is_heisa {
+                txt.extend_from_slice(line);
+                txt.push(b'\n');
+            }
+        }
+        txt.extend_from_slice(key);
+        txt.extend_from_slice(b"=checked\n");
+
+        let mut path = self.workspace.join(board.id());
+        path.push("SETTING.TXT");
+        write_atomically(&path, &txt)?;
+        self.reload_board_settings(board)?;
+        info!("retired a board, {}", board.id());
+        Ok(true)
+    }
+
+    /// Removes the board `id`. Its directory is renamed to
+    /// `.<id>.removed.<timestamp>` rather than deleted, which is ignored at
+    /// startup. Returns `Ok(false)` if there is no such board.
+    ///
+    /// Requests in progress may still read from the board, but find no
+    /// topic to write to, even after a board of the same id is created.
+    pub fn remove_board(&self, id: &str) -> io::Result<bool> {
+        let board = match self.boards.write().take(UncasedStr::new(id)) {
+            Some(board) => board,
+            None => return Ok(false),
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time before the UNIX epoch")
+            .as_secs();
+        let to = self.workspace.join(format!(".{}.removed.{}", board.id(), now));
+        let renamed = {
+            // Writes in progress finish before the directory is moved.
+            let mut topics = board.topics.write();
+            topics.set_closed(true);
+            let renamed = fs::rename(self.workspace.join(board.id()), &to);
+            if renamed.is_err() {
+                topics.set_closed(false);
+            }
+            renamed
+        };
+        if let Err(e) = renamed {
+            self.boards.write().insert(board);
+            return Err(e);
+        }
+
+        info!("removed a board, {}, to {:?}", board.id(), &to);
+        // `BoardRef`s handed out earlier may still refer to the board, but
+        // no more can be made.
+        let mut removed = self.removed.lock();
+        removed.retain(|b| ! b.is_unreferenced());
+        removed.push(board);
+        Ok(true)
+    }
+
+    /// Sets how often SETTING.TXT of each board is checked for changes, or
+    /// disables the check with `None`. The check is made on access to the
+    /// board, so no extra thread is involved.
+    pub fn set_settings_poll_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.settings_poll = interval;
+        self
+    }
+
+    /// Reloads SETTING.TXT of the board `id` if the file has been changed
+    /// since it was loaded. Requests in progress keep the previous settings.
+    ///
+    /// Returns whether the settings have been replaced.
+    pub fn reload_settings(&self, id: &str) -> io::Result<bool> {
+        match self.boards.read().get(UncasedStr::new(id)) {
+            Some(board) => self.reload_board_settings(board),
+            None => Ok(false),
+        }
+    }
+
+    /// Reloads SETTING.TXT of every board that has been changed.
+    pub fn reload_all_settings(&self) -> io::Result<()> {
+        for board in self.boards.read().iter() {
+            self.reload_board_settings(board)?;
+        }
+        Ok(())
+    }
+
+    fn reload_board_settings(&self, board: &Board) -> io::Result<bool> {
+        let mut path = self.workspace.join(board.id());
+        path.push("SETTING.TXT");
+        let current = board.settings();
+        let settings = match File::open(&path) {
+            Ok(f) => {
+                // The metadata cannot tell an edit that keeps the length
+                // within the resolution of the mtime, so the content is
+                // compared instead. SETTING.TXT is small enough.
+                let settings = Settings::load(&f)?;
+                if settings.as_ref() == current.as_ref() {
+                    return Ok(false);
+                }
+                settings
+            },
+            Err(ref e) if io::ErrorKind::NotFound == e.kind() => {
+                if Metadata::default() == *current.metadata() {
+                    return Ok(false);
+                }
+                Settings::empty()
+            },
+            Err(e) => return Err(e),
+        };
+        board.replace_settings(settings);
+        info!("reloaded {:?}", &path);
+        Ok(true)
+    }
+
+    /// Reloads the settings of `board` if they have not been checked for
+    /// `settings_poll`.
+    fn poll_settings(&self, board: &Board) {
+        let interval = match self.settings_poll {
+            Some(interval) => interval,
+            None => return,
+        };
+        // Requests coming while another one is checking the file go on with
+        // the current settings.
+        if let Some(mut checked) = board.settings_checked.try_lock() {
+            if checked.elapsed() < interval {
+                return;
+            }
+            *checked = Instant::now();
+            if let Err(e) = self.reload_board_settings(board) {
+                warn!("failed to reload SETTING.TXT of {}: {:?}", board.id(), e);
+            }
+        }
+    }
+
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) -> &mut Self {
+        self.sync_policy = policy;
+        self
+    }
+
     pub fn attach<M>(&mut self, middleware: M) -> &mut Self
         where M: BeforeMiddleware + AfterMiddleware + Send + Sync + 'static
     {
@@ -148,26 +348,327 @@ impl Bbs {
         self.middlewares.after()
     }
 
+    /// Prepares the middlewares for `post` before the topic is locked.
+    pub fn prepare_middlewares(&self, post: &Post, settings: &Settings)
+        -> middleware::Result<'static, ShareMap>
+    {
+        self.middlewares.prepare(post, settings)
+    }
+
     pub fn apply_middlewares<'a, 'r, 'b, 'k>(
         &self,
         post: &mut Post,
+        data: ShareMap,
         req: &middleware::Request<'a, 'r, 'b, 'k>,
         settings: &Settings,
     )
-        -> result::Result<(), Cow<'r, [u8]>>
+        -> middleware::Result<'r, ShareMap>
     {
-        self.middlewares.apply(post, req, settings)
+        self.middlewares.apply(post, data, req, settings)
+    }
+
+    pub fn notify_written(&self, post: &Post, data: &ShareMap) {
+        self.middlewares.written(post, data)
     }
 
     #[inline]
     pub fn board(&self, name: &str) -> Option<BoardRef> {
-        self.boards.get(UncasedStr::new(name)).map(|inner| BoardRef {
-            inner,
-            bbs: self,
+        let boards = self.boards.read();
+        boards.get(UncasedStr::new(name)).map(|inner| {
+            // Boards are never dropped before `self` even if removed.
+            let inner = unsafe { erase_lifetime::<Board>(inner) };
+            self.poll_settings(inner);
+            let (settings, settings_generation) = inner.settings_with_generation();
+            // Made while the boards are locked, so that a removed board is
+            // known to be unreferenced once its count drops to zero.
+            inner.acquire();
+            BoardRef { inner, bbs: self, settings, settings_generation }
         })
     }
 }
 
+const MISSING_FNAME: &str = "`DirEntry.path().file_name()` returned a `None`";
+
+fn is_board_id(id: &[u8]) -> bool {
+    ! id.is_empty() && id.iter().all(|&c| validator::is_alphanum(c))
+}
+
+/// Loads the board in the directory `path`, i.e. its SETTING.TXT, the dats
+/// under `dat` and the order of the topics from subject.txt.
+fn load_board(mut path: PathBuf, board_id: String) -> io::Result<Board> {
+    path.push("SETTING.TXT");
+    let settings = if path.exists() {
+        Settings::load(&File::open(&path)?)?
+    } else {
+        Settings::empty()
+    };
+
+    path.set_file_name("dat");
+    fs::create_dir_all(&path)?;
+    let mut topics = HashMap::new();
+    for ent in fs::read_dir(&path)? {
+        let ent = ent?;
+        let p = ent.path();
+        if ! p.is_file() { continue; }
+        let n = p.file_name().expect(MISSING_FNAME).as_bytes();
+        if ! n.ends_with(b".dat") { continue; }
+        let key = match ::atoi::atoi(&n[0..(n.len()-4)]) {
+            Some(::checked::Checked(Some(key))) => key,
+            _ => continue,
+        };
+        if 0 == repair_dat(&p)? {
+            // A topic whose first post has never been written.
+            warn!("removing an empty dat, {:?}", &p);
+            fs::remove_file(&p)?;
+            continue;
+        }
+        let mut reader = BufReader::new(File::open(&p)?);
+        let mut first = Vec::new();
+        reader.read_until(b'\n', &mut first)?;
+        let mut topic = Topic::load(key, (&first[..]).chain(reader))?;
+        topic.set_noname(self::topic::noname_command_in_line(&first));
+        let last = read_last_line(&p)?;
+        let stopped = self::topic::is_stopped_line(&last)
+            || self::topic::is_over_limit_line(&last);
+        let over_max_res = topic.post_count() >= max_res(&settings);
+        let full = over_max_res || ent.metadata()?.len() >= dat_max_bytes(&settings);
+        if full && ! stopped {
+            // The server went down before the stop line was appended.
+            let line = if over_max_res {
+                self::topic::over_max_res_line(max_res(&settings))
+            } else {
+                self::topic::over_max_size_line(dat_max_bytes(&settings) / 1024)
+            };
+            let mut f = fs::OpenOptions::new().append(true).open(&p)?;
+            f.write_all(&line)?;
+            f.sync_data()?;
+            *topic.post_count_mut() += 1;
+            warn!("appended the missing stop line to {:?}", &p);
+        }
+        if full || stopped {
+            topic.set_stopped(true);
+        }
+        topics.insert(key, topic);
+    }
+    path.set_file_name("kako");
+    repair_kako(&path)?;
+
+    // Restore the order of the topics from the last subject.txt.
+    // Topics missing from it are placed on the top.
+    path.set_file_name("subject.txt");
+    let mut order = Vec::with_capacity(topics.len());
+    if path.exists() {
+        let mut txt = Vec::new();
+        File::open(&path)?.read_to_end(&mut txt)?;
+        for line in txt.split(|&c| b'\n' == c) {
+            let key = memchr::memchr(b'.', line)
+                .and_then(|i| ::atoi::atoi(&line[..i]));
+            if let Some(::checked::Checked(Some(key))) = key {
+                order.push(key);
+            }
+        }
+    }
+    let listed: HashSet<u64> = order.iter().cloned().collect();
+    let mut rest: Vec<u64> = topics.keys()
+        .filter(|k| ! listed.contains(k))
+        .cloned()
+        .collect();
+    rest.sort();
+
+    let mut builder = Board::build(board_id, settings);
+    // `TopicsBuilder::insert` pushes to the front.
+    for key in order.iter().rev().chain(&rest) {
+        if let Some(topic) = topics.remove(key) {
+            builder.topic(*key, topic);
+        }
+    }
+    let board = builder.finish();
+
+    // Archive the topics beyond `BBS_MAX_THREAD`, e.g. after it has been
+    // lowered.
+    if let Some(&max) = board.settings().get::<common::MaxThread>() {
+        path.pop();
+        let mut topics = board.topics.write();
+        while topics.len() > max as usize {
+            let oldest = topics.back().map(Topic::id).expect("`Topics` is empty");
+            archive_dat(&path, oldest)?;
+            topics.remove(oldest);
+            info!("archived a topic over BBS_MAX_THREAD, {}/{}", board.id(), oldest);
+        }
+    }
+
+    Ok(board)
+}
+
+/// Moves the dat file of the topic `key` of the board in `dir` to the
+/// archive (_dat-ochi_).
+fn archive_dat(dir: &Path, key: u64) -> io::Result<()> {
+    let to = dir.join(board::kako_path(key));
+    fs::create_dir_all(to.parent().expect("`kako_path` has no parent"))?;
+    let mut from = dir.join("dat");
+    from.push(format!("{}.dat", key));
+    fs::rename(from, to)
+}
+
+/// Writes `contents` to a temporary file and renames it to `path`, so that
+/// readers never see a partially written file.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    {
+        let mut f = File::create(&tmp)?;
+        f.write_all(contents)?;
+        f.sync_data()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+fn max_res(settings: &Settings) -> usize {
+    settings.get::<common::MaxRes>().cloned().unwrap_or(DEFAULT_MAX_RES) as usize
+}
+
+fn dat_max_bytes(settings: &Settings) -> u64 {
+    settings.get::<common::DatMaxKb>().cloned().unwrap_or(DEFAULT_DAT_MAX_KB) as u64 * 1024
+}
+
+/// The operations on a dat file that `append_line` needs.
+trait DatFile: Write {
+    fn len(&self) -> io::Result<u64>;
+    fn set_len(&self, len: u64) -> io::Result<()>;
+    fn sync_data(&self) -> io::Result<()>;
+}
+
+impl DatFile for File {
+    fn len(&self) -> io::Result<u64> {
+        self.metadata().map(|m| m.len())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+/// Appends a line to a dat, truncating a partially written line on failure
+/// so that it is not glued to the next line.
+fn append_line<F: DatFile>(f: &mut F, line: &[u8], sync: bool) -> io::Result<()> {
+    let len = f.len()?;
+    let written = f.write_all(line)
+        .and_then(|()| if sync { f.sync_data() } else { Ok(()) });
+    if written.is_err() {
+        if let Err(e) = f.set_len(len) {
+            error!("failed to truncate a partially written line: {:?}", e);
+        }
+    }
+    written
+}
+
+/// Repairs the dats in the archive of a board, which may have been torn
+/// before they were archived.
+fn repair_kako(dir: &Path) -> io::Result<()> {
+    if ! dir.is_dir() {
+        return Ok(());
+    }
+    for ent in fs::read_dir(dir)? {
+        let p = ent?.path();
+        if p.is_dir() {
+            repair_kako(&p)?;
+        } else if p.file_name().map_or(false, |n| n.as_bytes().ends_with(b".dat")) {
+            repair_dat(&p)?;
+        }
+    }
+    Ok(())
+}
+
+/// Repairs a dat whose last line has been partially written because of a
+/// crash while appending, which would otherwise be miscounted and glued to
+/// the next post. The partial line is moved to the `.broken` sidecar of the
+/// dat, e.g. `1234567890.dat.broken`.
+///
+/// Returns the length of the dat after the repair.
+fn repair_dat(path: &Path) -> io::Result<u64> {
+    const CHUNK_LEN: u64 = 1024;
+
+    let mut f = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let len = f.metadata()?.len();
+
+    // Look for the end of the last complete line from the tail.
+    let mut buf = [0; CHUNK_LEN as usize];
+    let mut pos = len;
+    let keep = loop {
+        if 0 == pos {
+            break 0;
+        }
+        let n = cmp::min(pos, CHUNK_LEN);
+        pos -= n;
+        f.seek(SeekFrom::Start(pos))?;
+        f.read_exact(&mut buf[..(n as usize)])?;
+        if let Some(i) = memchr::memrchr(b'\n', &buf[..(n as usize)]) {
+            break pos + i as u64 + 1;
+        }
+    };
+    if keep == len {
+        return Ok(len);
+    }
+
+    let mut torn = Vec::with_capacity((len - keep) as usize);
+    f.seek(SeekFrom::Start(keep))?;
+    f.read_to_end(&mut torn)?;
+    torn.push(b'\n');
+
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".broken");
+    {
+        let mut broken = fs::OpenOptions::new().create(true).append(true).open(&sidecar)?;
+        broken.write_all(&torn)?;
+        broken.sync_all()?;
+    }
+    f.set_len(keep)?;
+    f.sync_all()?;
+
+    warn!("moved a partially written line of {} bytes from {:?} to {:?}",
+        len - keep, path, sidecar);
+    Ok(keep)
+}
+
+/// Reads the last line of a dat without the trailing newline. Lines longer
+/// than 1 KiB are truncated from the beginning.
+fn read_last_line(path: &Path) -> io::Result<Vec<u8>> {
+    const MAX_LEN: u64 = 1024;
+
+    let mut f = File::open(path)?;
+    let len = f.metadata()?.len();
+    f.seek(SeekFrom::Start(len.saturating_sub(MAX_LEN)))?;
+    let mut buf = Vec::with_capacity(cmp::min(len, MAX_LEN) as usize);
+    f.read_to_end(&mut buf)?;
+    if Some(&b'\n') == buf.last() {
+        buf.pop();
+    }
+    let start = memchr::memrchr(b'\n', &buf).map_or(0, |i| i + 1);
+    Ok(buf.split_off(start))
+}
+
+impl<'a> Clone for BoardRef<'a> {
+    fn clone(&self) -> Self {
+        self.inner.acquire();
+        BoardRef {
+            inner: self.inner,
+            bbs: self.bbs,
+            settings: Arc::clone(&self.settings),
+            settings_generation: self.settings_generation,
+        }
+    }
+}
+
+impl<'a> Drop for BoardRef<'a> {
+    fn drop(&mut self) {
+        self.inner.release();
+    }
+}
+
 impl<'a, 'r> FromRequest<'a, 'r> for &'r Bbs {
     type Error = ();
 
@@ -177,18 +678,55 @@ impl<'a, 'r> FromRequest<'a, 'r> for &'r Bbs {
 }
 
 impl<'a> BoardRef<'a> {
-    pub fn id(&self) -> &'a str {
+    /// Returns the id of the board, which must not outlive `self` since a
+    /// removed board is freed once no `BoardRef` refers to it.
+    pub fn id(&self) -> &str {
         self.inner.id()
     }
 
-    pub fn settings(&self) -> &'a Settings {
-        self.inner.settings()
+    /// Returns the snapshot of the settings taken when the board has been
+    /// looked up.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
     }
 
     pub fn subject_txt(&self) -> Arc<SubjectTxt> {
         self.inner.subject_txt()
     }
 
+    /// Returns the board top page, which is cached until the next change of
+    /// subject.txt.
+    ///
+    /// The dats are read without holding the lock of the topics so that
+    /// posting is not blocked meanwhile.
+    pub fn index_html(&self) -> Arc<IndexHtml> {
+        let (snapshot, generation) = {
+            let topics = self.inner.topics.read();
+            if let Some(html) = topics.cached_index_html() {
+                return Arc::clone(html);
+            }
+            (board::index_html::Snapshot::new(self, &topics), topics.generation())
+        };
+        let mut html = Vec::new();
+        board::index_html::make(self, &snapshot, &mut html);
+        self.inner.topics.read().cache_index_html(generation, self.settings_generation, html)
+    }
+
+    /// Returns the topic list in JSON, which is cached until the next change
+    /// of subject.txt.
+    pub fn subject_json(&self) -> Arc<SubjectJson> {
+        let (snapshot, generation) = {
+            let topics = self.inner.topics.read();
+            if let Some(json) = topics.cached_subject_json() {
+                return Arc::clone(json);
+            }
+            (board::subject_json::Snapshot::new(&topics), topics.generation())
+        };
+        let mut json = Vec::new();
+        board::subject_json::make(self, snapshot, &mut json);
+        self.inner.topics.read().cache_subject_json(generation, json)
+    }
+
     pub fn topic(&self, key: u64) -> Option<TopicRef> {
         let guard = self.inner.topics.read();
         OwningRef::new(guard)
@@ -198,15 +736,19 @@ impl<'a> BoardRef<'a> {
     }
 
     pub fn topic_mut(&'a self, key: u64) -> Option<TopicMut<'a>> {
-        let guard = self.inner.topics.write();
-        OwningRefMut::new(guard)
-            .try_map_mut(|topics| topics.get_mut(key).ok_or(()))
-            .ok()
-            .map(|inner| TopicMut { inner, board: self })
+        let topics = self.inner.topics.write();
+        if topics.get(key).is_none() {
+            return None;
+        }
+        Some(TopicMut { topics, key, board: self })
     }
 
-    pub fn create_topic(&'a self, title: Vec<u8>) -> TopicMut<'a> {
+    /// Creates a topic, or returns `None` if the board has been removed.
+    pub fn create_topic(&'a self, title: Vec<u8>) -> Option<TopicMut<'a>> {
         let mut guard = self.inner.topics.write();
+        if guard.is_closed() {
+            return None;
+        }
         let mut id = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("system time before the UNIX epoch")
@@ -215,14 +757,216 @@ impl<'a> BoardRef<'a> {
         let _ret = guard.insert(Topic::new(id, title, 0));
         debug_assert!(_ret.is_none());
 
-        let inner = OwningRefMut::new(guard)
-            .map_mut(|topics| topics.get_mut(id).unwrap());
-        TopicMut { inner, board: self }
+        Some(TopicMut { topics: guard, key: id, board: self })
+    }
+
+    /// Stops the topic `key` on behalf of a moderator, appending the stop
+    /// line to the dat. Returns `Ok(false)` if there is no such topic or it
+    /// has already been stopped.
+    pub fn stop_topic(&'a self, key: u64) -> io::Result<bool> {
+        let t = match self.topic_mut(key) {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+        if t.is_stopped() {
+            return Ok(false);
+        }
+        t.into_dat()?.stop(&topic::stopped_line())?;
+        Ok(true)
+    }
+
+    /// Replaces the post `number` (1-based) of the topic `key` with the
+    /// "あぼーん" line on behalf of a moderator, keeping the numbering of the
+    /// later posts. Returns `Ok(false)` if there is no such post.
+    ///
+    /// Fails with `InvalidInput` for the line that stops the topic.
+    pub fn delete_post(&self, key: u64, number: usize) -> io::Result<bool> {
+        let mut topics = self.inner.topics.write();
+        let (title, post_count) = match topics.get(key) {
+            Some(t) if 0 < number && number <= t.post_count() => {
+                (t.title().to_vec(), t.post_count())
+            },
+            _ => return Ok(false),
+        };
+        self.rewrite_dat(key, post_count, |i, line| {
+            if i + 1 != number {
+                return Ok(None);
+            }
+            if topic::is_stopped_line(line) || topic::is_over_limit_line(line) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the stop line"));
+            }
+            // The first line keeps the title.
+            Ok(Some(topic::deleted_line(if 0 == i { &title[..] } else { &b""[..] })))
+        })?;
+        // The digests in index.html and the mtime in subject.json are stale.
+        topics.reset_txt(false);
+        info!("deleted a post, {}/{}/{}", self.id(), key, number);
+        Ok(true)
+    }
+
+    /// Removes the topic `key` and its dat on behalf of a moderator.
+    /// Returns `Ok(false)` if there is no such topic.
+    ///
+    /// On failure, the topic is left as it was, both on the disk and in
+    /// memory.
+    pub fn delete_topic(&self, key: u64) -> io::Result<bool> {
+        let mut topics = self.inner.topics.write();
+        if topics.get(key).is_none() {
+            return Ok(false);
+        }
+
+        // The dat is moved aside first, which is ignored at startup, so
+        // that it can be put back if subject.txt fails to be written.
+        let path = self.dat_path(key);
+        let mut aside = path.as_os_str().to_owned();
+        aside.push(".deleted");
+        let moved = match fs::rename(&path, &aside) {
+            Ok(()) => true,
+            Err(ref e) if io::ErrorKind::NotFound == e.kind() => false,
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = topics.persist_without(&self.subject_txt_path(), key) {
+            if moved {
+                if let Err(e) = fs::rename(&aside, &path) {
+                    error!("failed to restore the dat of a topic, {}/{}: {:?}", self.id(), key, e);
+                }
+            }
+            return Err(e);
+        }
+        topics.remove(key);
+        if moved {
+            if let Err(e) = fs::remove_file(&aside) {
+                warn!("failed to remove {:?}: {:?}", &aside, e);
+            }
+        }
+        info!("deleted a topic, {}/{}", self.id(), key);
+        Ok(true)
+    }
+
+    /// Replaces the title of the topic `key` on behalf of a moderator.
+    /// `title` must be HTML-escaped Shift_JIS as in the dat. Returns
+    /// `Ok(false)` if there is no such topic.
+    ///
+    /// On failure, the title is left as it was in the dat and in memory.
+    pub fn edit_title(&self, key: u64, title: Vec<u8>) -> io::Result<bool> {
+        const REMOVED: &str = "the topic has been removed while locked";
+
+        let mut topics = self.inner.topics.write();
+        let (old, post_count) = match topics.get(key) {
+            Some(t) => (t.title().to_vec(), t.post_count()),
+            None => return Ok(false),
+        };
+
+        topics.get_mut(key).expect(REMOVED).set_title(title.clone());
+        topics.reset_txt(false);
+        let path = self.subject_txt_path();
+        let edited = topics.persist(&path).and_then(|()| {
+            self.rewrite_dat(key, post_count, |i, line| {
+                if 0 != i {
+                    return Ok(None);
+                }
+                let mut record = dat::parse_line(line).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "malformed first line")
+                })?;
+                record.title = title.clone();
+                let mut new = Vec::with_capacity(line.len() + title.len());
+                record.write_to(&mut new)?;
+                Ok(Some(new))
+            })
+        });
+        if let Err(e) = edited {
+            topics.get_mut(key).expect(REMOVED).set_title(old);
+            topics.reset_txt(false);
+            if let Err(e) = topics.persist(&path) {
+                warn!("failed to write subject.txt of {}: {:?}", self.id(), e);
+            }
+            return Err(e);
+        }
+        info!("edited the title of a topic, {}/{}", self.id(), key);
+        Ok(true)
+    }
+
+    /// Rewrites the dat of the topic `key` line by line, replacing the
+    /// lines for which `f` returns a new line. `f` takes the 0-based line
+    /// number and the line without the trailing newline, and the new line
+    /// must have one.
+    ///
+    /// Fails with `InvalidData` without rewriting if the dat does not have
+    /// `post_count` lines, i.e. a line per post.
+    ///
+    /// The caller must hold the lock of the topics so that no post is
+    /// appended meanwhile. The dat is replaced at once, so readers see
+    /// either the old or the new one.
+    fn rewrite_dat<F>(&self, key: u64, post_count: usize, mut f: F) -> io::Result<()>
+        where F: FnMut(usize, &[u8]) -> io::Result<Option<Vec<u8>>>
+    {
+        let path = self.dat_path(key);
+        let mut dat = Vec::new();
+        File::open(&path)?.read_to_end(&mut dat)?;
+
+        let mut new = Vec::with_capacity(dat.len());
+        let mut rest = &dat[..];
+        let mut i = 0;
+        while ! rest.is_empty() {
+            let end = memchr::memchr(b'\n', rest).unwrap_or(rest.len());
+            let line = &rest[..end];
+            match f(i, line)? {
+                Some(replaced) => new.extend_from_slice(&replaced),
+                None => {
+                    new.extend_from_slice(line);
+                    new.push(b'\n');
+                },
+            }
+            rest = &rest[cmp::min(end + 1, rest.len())..];
+            i += 1;
+        }
+        if post_count != i {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a line per post"));
+        }
+
+        write_atomically(&path, &new)
     }
 
     pub fn bbs(&self) -> &'a Bbs {
         &self.bbs
     }
+
+    /// Returns the path to the persisted subject.txt of the board.
+    pub fn subject_txt_path(&self) -> PathBuf {
+        let mut path = self.bbs.workspace.join(self.id());
+        path.push("subject.txt");
+        path
+    }
+
+    /// Returns the path to the archived dat file of the topic `key`.
+    pub fn kako_path(&self, key: u64) -> PathBuf {
+        let mut path = self.bbs.workspace.join(self.id());
+        path.push(board::kako_path(key));
+        path
+    }
+
+    /// Moves the dat file of the topic `key` to the archive (_dat-ochi_).
+    ///
+    /// This does not remove the topic from `Topics`.
+    fn archive(&self, key: u64) -> io::Result<()> {
+        archive_dat(&self.bbs.workspace.join(self.id()), key)
+    }
+
+    /// Returns the path to the dat file of the topic `key`,
+    /// i.e. `<workspace>/<board>/dat/<key>.dat`.
+    pub fn dat_path(&self, key: u64) -> PathBuf {
+        let mut path = OsString::with_capacity(
+            self.bbs.workspace.as_os_str().len()
+            + self.id().len()
+            + "//dat/0000000000.dat".len()
+        );
+        path.push(&*self.bbs.workspace);
+        let mut path: PathBuf = path.into();
+        path.push(self.id());
+        path.push("dat");
+        path.push(format!("{}.dat", key));
+        path
+    }
 }
 
 impl<'a> Deref for BoardRef<'a> {
@@ -233,6 +977,20 @@ impl<'a> Deref for BoardRef<'a> {
     }
 }
 
+impl<'a> TopicRef<'a> {
+    /// Reads the whole dat file of the topic.
+    ///
+    /// The dat is read while the topic is locked, so the result never
+    /// contains a partially written line.
+    pub fn dat(&self) -> io::Result<Dat> {
+        let mut f = File::open(self.board.dat_path(self.id()))?;
+        let m = f.metadata()?;
+        let mut buf = Vec::with_capacity(m.len() as usize);
+        f.read_to_end(&mut buf)?;
+        Ok(Cacheable::new(buf, (&m).into()))
+    }
+}
+
 impl<'a> Deref for TopicRef<'a> {
     type Target = Topic;
 
@@ -242,67 +1000,496 @@ impl<'a> Deref for TopicRef<'a> {
 }
 
 impl<'a> TopicMut<'a> {
-    pub fn into_dat(self) -> io::Result<DatRef<'a>> {
-        let mut path = OsString::with_capacity(
-            self.board.bbs.workspace.as_os_str().len()
-            + self.board.id().len()
-            + "//dat/0000000000.dat".len()
-        );
-        path.push(&*self.board.bbs.workspace);
-        let mut path: PathBuf = path.into();
-        path.push(self.board.id());
-        path.push("dat");
-        path.push(format!("{}.dat", self.id()));
-
-        let inner = fs::OpenOptions::new()
+    /// Opens the dat of the topic for appending. A topic just created is
+    /// removed again if its dat cannot be opened.
+    pub fn into_dat(mut self) -> io::Result<DatRef<'a>> {
+        let path = self.board.dat_path(self.id());
+        let opened = fs::OpenOptions::new()
             .create(true)
             .append(true)
-            .open(path)?;
+            .open(path);
 
-        Ok(DatRef { inner, topic: self })
+        match opened {
+            Ok(inner) => Ok(DatRef { inner, topic: self }),
+            Err(e) => {
+                if self.post_count() == 0 {
+                    let key = self.key;
+                    self.topics.remove(key);
+                }
+                Err(e)
+            },
+        }
     }
 }
 
+const REMOVED_WHILE_LOCKED: &str = "the topic has been removed while locked";
+
 impl<'a> Deref for TopicMut<'a> {
     type Target = Topic;
 
     fn deref(&self) -> &Topic {
-        &self.inner
+        self.topics.get(self.key).expect(REMOVED_WHILE_LOCKED)
     }
 }
 
 impl<'a> DerefMut for TopicMut<'a> {
     fn deref_mut(&mut self) -> &mut Topic {
-        &mut self.inner
+        self.topics.get_mut(self.key).expect(REMOVED_WHILE_LOCKED)
     }
 }
 
 impl<'a> DatRef<'a> {
     pub fn increment_post_count(&mut self) {
         *self.post_count_mut() += 1;
-        self.reset_txt(true);
+        self.topic.topics.reset_txt(true);
+    }
+
+    /// Moves the topic to the top of subject.txt (i.e. _age_).
+    pub fn bump(&mut self) {
+        let key = self.topic.key;
+        self.topic.topics.bump(key);
+    }
+
+    /// Writes subject.txt to the disk so that the order of the topics
+    /// survives a restart. It is only written if the order has changed, and
+    /// at most once per `PERSIST_INTERVAL_SECS`; a pending change is written
+    /// by a later post.
+    pub fn persist_subject_txt(&mut self) -> io::Result<()> {
+        let path = self.topic.board.subject_txt_path();
+        let interval = Duration::from_secs(PERSIST_INTERVAL_SECS);
+        self.topic.topics.persist_order(&path, interval).map(|_| ())
+    }
+
+    /// Stops the topic if it has reached `BBS_MAX_RES` posts or its dat has
+    /// reached `BBS_DAT_MAX_KB`, appending the stop line to the dat.
+    pub fn stop_if_full(&mut self) -> io::Result<bool> {
+        let board = self.topic.board;
+        let settings = board.settings();
+        let line = if self.post_count() >= max_res(settings) {
+            topic::over_max_res_line(max_res(settings))
+        } else if self.inner.metadata()?.len() >= dat_max_bytes(settings) {
+            topic::over_max_size_line(dat_max_bytes(settings) / 1024)
+        } else {
+            return Ok(false);
+        };
+        self.stop(&line)?;
+        Ok(true)
+    }
+
+    /// Appends `line` to the dat and marks the topic as stopped.
+    pub fn stop(&mut self, line: &[u8]) -> io::Result<()> {
+        self.append(line)?;
+        self.topic.set_stopped(true);
+        self.increment_post_count();
+        Ok(())
+    }
+
+    /// Appends a line to the dat, and syncs it according to the
+    /// `SyncPolicy` of the `Bbs`.
+    ///
+    /// A partially written line is truncated on failure so that it is not
+    /// glued to the next line.
+    pub fn append(&mut self, line: &[u8]) -> io::Result<()> {
+        let sync = SyncPolicy::Always == self.topic.board.bbs.sync_policy;
+        append_line(&mut self.inner, line, sync)
+    }
+
+    /// Archives the oldest topics while the board has more topics than
+    /// `BBS_MAX_THREAD`. This is done once the first post of a new topic
+    /// has been written, so a rejected topic never pushes a live one out.
+    pub fn archive_overflow(&mut self) -> io::Result<()> {
+        let board = self.topic.board;
+        let max = match board.settings().get::<common::MaxThread>() {
+            Some(&max) => max as usize,
+            None => return Ok(()),
+        };
+        let key = self.topic.key;
+        let topics = &mut self.topic.topics;
+        while topics.len() > max {
+            let oldest = topics.back().map(Topic::id).expect("`Topics` is empty");
+            if oldest == key { break; }
+            board.archive(oldest)?;
+            topics.remove(oldest);
+        }
+        Ok(())
+    }
+
+    /// Removes the topic and its dat if nothing has been written to it,
+    /// e.g. when the first post of a new topic has been rejected.
+    pub fn abandon(self) -> io::Result<()> {
+        if self.post_count() > 0 {
+            return Ok(());
+        }
+
+        let id = self.id();
+        let path = self.topic.board.dat_path(id);
+        let DatRef { inner, mut topic } = self;
+        drop(inner);
+        topic.topics.remove(id);
+        fs::remove_file(path)
     }
 
     fn post_count_mut(&mut self) -> &mut usize {
         self.topic.post_count_mut()
     }
+}
 
-    fn reset_txt(&mut self, addition: bool) {
-        unsafe {
-            // This is sound because `Topics::reset_txt` does not change
-            // location of `Topic`s.
-            let topics = OwningRefMut::owner(&self.topic.inner);
-            (*(&**topics as *const Topics as *mut Topics)).reset_txt(addition);
-        }
+impl<'a> Deref for DatRef<'a> {
+    type Target = Topic;
+
+    fn deref(&self) -> &Topic {
+        &self.topic
     }
 }
 
-impl<'a> io::Write for DatRef<'a> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        io::Write::write(&mut self.inner, buf)
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use util::temp_dir;
+
+    /// A dat on which writes fail after `limit` bytes.
+    struct Flaky {
+        buf: RefCell<Vec<u8>>,
+        limit: usize,
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        io::Write::flush(&mut self.inner)
+    impl Write for Flaky {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            let mut buf = self.buf.borrow_mut();
+            let n = cmp::min(data.len(), self.limit.saturating_sub(buf.len()));
+            if 0 == n {
+                return Err(io::Error::new(io::ErrorKind::Other, "disk full"));
+            }
+            buf.extend_from_slice(&data[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl DatFile for Flaky {
+        fn len(&self) -> io::Result<u64> {
+            Ok(self.buf.borrow().len() as u64)
+        }
+
+        fn set_len(&self, len: u64) -> io::Result<()> {
+            self.buf.borrow_mut().truncate(len as usize);
+            Ok(())
+        }
+
+        fn sync_data(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sidecar(path: &Path) -> Vec<u8> {
+        let mut p = path.as_os_str().to_owned();
+        p.push(".broken");
+        fs::read(p).unwrap_or_default()
+    }
+
+    #[test]
+    fn append() {
+        let mut f = Flaky { buf: RefCell::new(b"a<>b<>c<> d <>\n".to_vec()), limit: 20 };
+        assert!(append_line(&mut f, b"e<>f<>g<> h <>\n", true).is_err());
+        assert_eq!(b"a<>b<>c<> d <>\n".to_vec(), *f.buf.borrow());
+
+        f.limit = 100;
+        append_line(&mut f, b"e<>f<>g<> h <>\n", true).unwrap();
+        assert_eq!(b"a<>b<>c<> d <>\ne<>f<>g<> h <>\n".to_vec(), *f.buf.borrow());
+    }
+
+    #[test]
+    fn repair_torn_line() {
+        let path = temp_dir("repair-torn").join("1.dat");
+        // The torn line is longer than the chunks read from the tail.
+        let torn = vec![b'x'; 3000];
+        let mut dat = b"a<>b<>c<> d <>title\n".to_vec();
+        dat.extend_from_slice(&torn);
+        fs::write(&path, &dat).unwrap();
+
+        assert_eq!(20, repair_dat(&path).unwrap());
+        assert_eq!(b"a<>b<>c<> d <>title\n".to_vec(), fs::read(&path).unwrap());
+        let mut expected = torn.clone();
+        expected.push(b'\n');
+        assert_eq!(expected, sidecar(&path));
+
+        // An intact dat is left alone.
+        assert_eq!(20, repair_dat(&path).unwrap());
+        assert_eq!(expected, sidecar(&path));
+    }
+
+    #[test]
+    fn repair_without_newline() {
+        let path = temp_dir("repair-no-newline").join("1.dat");
+        fs::write(&path, b"a<>b<>c<> d").unwrap();
+        assert_eq!(0, repair_dat(&path).unwrap());
+        assert!(fs::read(&path).unwrap().is_empty());
+
+        // The sidecar accumulates the torn lines.
+        fs::write(&path, b"e<>f").unwrap();
+        assert_eq!(0, repair_dat(&path).unwrap());
+        assert_eq!(b"a<>b<>c<> d\ne<>f\n".to_vec(), sidecar(&path));
+    }
+
+    #[test]
+    fn repair_archive() {
+        let dir = temp_dir("repair-kako").join("kako");
+        let path = dir.join(board::kako_path(1234567890).trim_left_matches("kako/"));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"a<>b<>c<> d <>title\ne<>f").unwrap();
+        repair_kako(&dir).unwrap();
+        assert_eq!(b"a<>b<>c<> d <>title\n".to_vec(), fs::read(&path).unwrap());
+        assert_eq!(b"e<>f\n".to_vec(), sidecar(&path));
+    }
+
+    fn workspace(name: &str, setting_txt: &[u8]) -> PathBuf {
+        let dir = temp_dir(name);
+        fs::create_dir(dir.join("news")).unwrap();
+        fs::write(dir.join("news").join("SETTING.TXT"), setting_txt).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reload_settings() {
+        let dir = workspace("reload", b"BBS_TITLE=a\n");
+        let mut bbs = Bbs::with_workspace(&dir).unwrap();
+        bbs.set_settings_poll_interval(None);
+        let before = bbs.board("news").unwrap();
+        let metadata = before.settings().metadata().clone();
+        assert!(! bbs.reload_settings("news").unwrap());
+
+        // Likely within the same second and of the same length.
+        fs::write(dir.join("news").join("SETTING.TXT"), b"BBS_TITLE=b\n").unwrap();
+        assert!(bbs.reload_settings("news").unwrap());
+        let after = bbs.board("news").unwrap();
+        assert_eq!(b"b", &after.settings().get::<common::Title>().unwrap()[..]);
+        assert!(metadata != *after.settings().metadata());
+
+        // A board looked up earlier keeps its snapshot.
+        assert_eq!(b"a", &before.settings().get::<common::Title>().unwrap()[..]);
+    }
+
+    #[test]
+    fn index_html_after_reload() {
+        let dir = workspace("reload-index", b"BBS_TITLE=old\n");
+        let mut bbs = Bbs::with_workspace(&dir).unwrap();
+        bbs.set_settings_poll_interval(None);
+        let before = bbs.board("news").unwrap();
+
+        fs::write(dir.join("news").join("SETTING.TXT"), b"BBS_TITLE=new\n").unwrap();
+        assert!(bbs.reload_settings("news").unwrap());
+
+        // The page made from the old snapshot is not cached.
+        let stale = before.index_html();
+        let after = bbs.board("news").unwrap();
+        let fresh = after.index_html();
+        assert!(! Arc::ptr_eq(&stale, &fresh));
+        assert!(fresh.body().windows(3).any(|w| w == b"new"));
+        assert!(Arc::ptr_eq(&fresh, &after.index_html()));
+    }
+
+    #[test]
+    fn board_ids() {
+        assert!(is_board_id(b"news4vip"));
+        assert!(! is_board_id(b""));
+        assert!(! is_board_id(b"../news"));
+        assert!(! is_board_id(b".news.removed.1"));
+    }
+
+    #[test]
+    fn create_board() {
+        let dir = temp_dir("create-board");
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        bbs.create_board("news", b"BBS_TITLE=news\n").unwrap();
+        let brd = bbs.board("NEWS").unwrap();
+        assert_eq!(b"news", &brd.settings().get::<common::Title>().unwrap()[..]);
+        assert!(dir.join("news").join("dat").is_dir());
+
+        let kind = |r: io::Result<()>| r.unwrap_err().kind();
+        assert_eq!(io::ErrorKind::AlreadyExists, kind(bbs.create_board("News", b"")));
+        assert_eq!(io::ErrorKind::InvalidInput, kind(bbs.create_board("", b"")));
+        assert_eq!(io::ErrorKind::InvalidInput, kind(bbs.create_board("a/b", b"")));
+    }
+
+    #[test]
+    fn retire_board() {
+        let dir = workspace("retire-board", b"BBS_TITLE=news\nBBS_HEISA=\n");
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        assert!(bbs.retire_board("news").unwrap());
+        assert!(! bbs.retire_board("nope").unwrap());
+
+        let brd = bbs.board("news").unwrap();
+        assert_eq!(Some(&true), brd.settings().get::<common::Heisa>());
+        assert_eq!(b"news", &brd.settings().get::<common::Title>().unwrap()[..]);
+        // It is kept across restarts.
+        drop(brd);
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        assert_eq!(Some(&true), bbs.board("news").unwrap().settings().get::<common::Heisa>());
+    }
+
+    #[test]
+    fn remove_board() {
+        let dir = workspace("remove-board", b"");
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        let stale = bbs.board("news").unwrap();
+        assert!(bbs.remove_board("news").unwrap());
+        assert!(! bbs.remove_board("news").unwrap());
+        assert!(bbs.board("news").is_none());
+        assert!(! dir.join("news").exists());
+
+        // A reference to the removed board does not write to the new one.
+        bbs.create_board("news", b"").unwrap();
+        assert!(stale.create_topic(b"title".to_vec()).is_none());
+        let brd = bbs.board("news").unwrap();
+        let key = brd.create_topic(b"title".to_vec()).unwrap().id();
+        assert!(stale.topic_mut(key).is_none());
+
+        // The removed board is freed once unreferenced.
+        drop(stale);
+        drop(brd);
+        assert!(bbs.remove_board("news").unwrap());
+        assert_eq!(1, bbs.removed.lock().len());
+    }
+
+    #[test]
+    fn load_stopped() {
+        let dir = workspace("load-stopped", b"");
+        let path = dir.join("news").join("dat");
+        fs::create_dir(&path).unwrap();
+        // "停止しました。。。"
+        let mut spoofed = b"a<>b<>c<> d <>title\n\
+            \x92\xE2\x8E\x7E\x82\xB5\x82\xDC\x82\xB5\x82\xBD\x81\x42\x81\x42\x81\x42".to_vec();
+        spoofed.extend_from_slice(b"<><>2018/01/01(Mon) 00:00:00.00<> e <>\n");
+        fs::write(path.join("1.dat"), &spoofed).unwrap();
+        let mut stopped = b"a<>b<>c<> d <>title\n".to_vec();
+        stopped.extend_from_slice(&topic::stopped_line());
+        fs::write(path.join("2.dat"), &stopped).unwrap();
+
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        let brd = bbs.board("news").unwrap();
+        assert!(! brd.topic(1).unwrap().is_stopped());
+        assert!(brd.topic(2).unwrap().is_stopped());
+    }
+
+    const DAT: &[u8] = b"a<>b<>c<> first <>title\nd<>e<>f<> second <>\ng<>h<>i<> third <>\n";
+
+    /// Makes a workspace with a topic of three posts, `1234567890.dat`.
+    fn moderated(name: &str) -> PathBuf {
+        let dir = workspace(name, b"");
+        fs::create_dir(dir.join("news").join("dat")).unwrap();
+        fs::write(dir.join("news").join("dat").join("1234567890.dat"), DAT).unwrap();
+        dir
+    }
+
+    fn lines(dat: &[u8]) -> Vec<Vec<u8>> {
+        dat.split(|&c| b'\n' == c).filter(|l| ! l.is_empty()).map(|l| l.to_vec()).collect()
+    }
+
+    #[test]
+    fn delete_post() {
+        let dir = moderated("delete-post");
+        let path = dir.join("news").join("dat").join("1234567890.dat");
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        let brd = bbs.board("news").unwrap();
+
+        assert!(brd.delete_post(1234567890, 2).unwrap());
+        let mut deleted = topic::deleted_line(b"");
+        deleted.pop();
+        assert_eq!(deleted, lines(&fs::read(&path).unwrap())[1]);
+
+        // The first post keeps the title.
+        assert!(brd.delete_post(1234567890, 1).unwrap());
+        let first = lines(&fs::read(&path).unwrap()).swap_remove(0);
+        assert_eq!(b"title", &dat::parse_line(&first).unwrap().title[..]);
+        assert_eq!(3, lines(&fs::read(&path).unwrap()).len());
+
+        assert!(! brd.delete_post(1234567890, 0).unwrap());
+        assert!(! brd.delete_post(1234567890, 4).unwrap());
+        assert!(! brd.delete_post(1, 1).unwrap());
+
+        // The stop line is left alone.
+        assert!(brd.stop_topic(1234567890).unwrap());
+        let stopped = fs::read(&path).unwrap();
+        let kind = brd.delete_post(1234567890, 4).unwrap_err().kind();
+        assert_eq!(io::ErrorKind::InvalidInput, kind);
+        assert_eq!(stopped, fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn moderate_unusual_lines() {
+        let dir = workspace("moderate-unusual", b"");
+        let path = dir.join("news").join("dat").join("1.dat");
+        fs::create_dir(dir.join("news").join("dat")).unwrap();
+        // A body without the spaces, and a post under the name of the stop
+        // line, "停止しました。。。".
+        let mut dat = b"a<>b<>c<>first<>title\n\
+            \x92\xE2\x8E\x7E\x82\xB5\x82\xDC\x82\xB5\x82\xBD\x81\x42\x81\x42\x81\x42".to_vec();
+        dat.extend_from_slice(b"<><>2018/01/01(Mon) 00:00:00.00<> spam <>\n");
+        fs::write(&path, &dat).unwrap();
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        let brd = bbs.board("news").unwrap();
+
+        assert!(brd.delete_post(1, 2).unwrap());
+        assert!(brd.edit_title(1, b"new".to_vec()).unwrap());
+        let mut expected = b"a<>b<>c<>first<>new\n".to_vec();
+        expected.extend_from_slice(&topic::deleted_line(b""));
+        assert_eq!(expected, fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn delete_topic() {
+        let dir = moderated("delete-topic");
+        let path = dir.join("news").join("dat").join("1234567890.dat");
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        let brd = bbs.board("news").unwrap();
+
+        assert!(brd.delete_topic(1234567890).unwrap());
+        assert!(! brd.delete_topic(1234567890).unwrap());
+        assert!(brd.topic(1234567890).is_none());
+        // Nor is a dat moved aside left behind.
+        assert!(! path.exists());
+        assert!(fs::read_dir(dir.join("news").join("dat")).unwrap().next().is_none());
+        assert!(fs::read(brd.subject_txt_path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn edit_title() {
+        let dir = moderated("edit-title");
+        let path = dir.join("news").join("dat").join("1234567890.dat");
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        let brd = bbs.board("news").unwrap();
+
+        assert!(brd.edit_title(1234567890, b"new".to_vec()).unwrap());
+        assert!(! brd.edit_title(1, b"new".to_vec()).unwrap());
+        let edited = fs::read(&path).unwrap();
+        assert_eq!(b"new", &dat::parse_line(&lines(&edited)[0]).unwrap().title[..]);
+        assert_eq!(&DAT[24..], &edited[22..]);
+        assert_eq!(b"new", brd.topic(1234567890).unwrap().title());
+        let txt = fs::read(brd.subject_txt_path()).unwrap();
+        assert_eq!(b"1234567890.dat<>new (3)\n".to_vec(), txt);
+    }
+
+    #[test]
+    fn moderate_without_subject_txt() {
+        let dir = moderated("moderate-unwritable");
+        let path = dir.join("news").join("dat").join("1234567890.dat");
+        let bbs = Bbs::with_workspace(&dir).unwrap();
+        let brd = bbs.board("news").unwrap();
+        // subject.txt cannot be replaced by a directory.
+        let _ = fs::remove_file(brd.subject_txt_path());
+        fs::create_dir(brd.subject_txt_path()).unwrap();
+
+        assert!(brd.edit_title(1234567890, b"new".to_vec()).is_err());
+        assert_eq!(b"title", brd.topic(1234567890).unwrap().title());
+        assert!(brd.subject_txt().body().windows(5).any(|w| w == b"title"));
+
+        assert!(brd.delete_topic(1234567890).is_err());
+        assert!(brd.topic(1234567890).is_some());
+        assert_eq!(DAT.to_vec(), fs::read(&path).unwrap());
     }
 }
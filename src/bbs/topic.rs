@@ -1,17 +1,29 @@
 //! Monaxide internally uses the term _topic_ to refer to a BBS thread
 //! in order to avoid confusion with `std::thread`.
 
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::mem;
 
 use memchr;
 
+use super::dat;
+
 pub struct Topic {
     id: u64,
     title: Box<[u8]>,
     post_count: usize,
+    stopped: bool,
+    noname: Option<Box<[u8]>>,
 }
 
+// "このスレッドは"
+const THIS_TOPIC: &[u8] = b"\x82\xB1\x82\xCC\x83\x58\x83\x8C\x83\x62\x83\x68\x82\xCD";
+// "を超えました。"
+const EXCEEDED: &[u8] = b"\x82\xF0\x92\xB4\x82\xA6\x82\xDC\x82\xB5\x82\xBD\x81\x42";
+// "新しいスレッドを立ててください。"
+const CREATE_NEW: &[u8] = b"\x90\x56\x82\xB5\x82\xA2\x83\x58\x83\x8C\x83\x62\x83\x68\x82\xF0\
+    \x97\xA7\x82\xC4\x82\xC4\x82\xAD\x82\xBE\x82\xB3\x82\xA2\x81\x42";
+
 impl Topic {
     #[inline]
     pub fn new(id: u64, title: Vec<u8>, post_count: usize) -> Self {
@@ -19,6 +31,8 @@ impl Topic {
             id,
             title: title.into(),
             post_count,
+            stopped: false,
+            noname: None,
         }
     }
 
@@ -105,6 +119,11 @@ impl Topic {
         &self.title
     }
 
+    #[inline]
+    pub fn set_title(&mut self, title: Vec<u8>) {
+        self.title = title.into();
+    }
+
     #[inline]
     pub fn post_count(&self) -> usize {
         self.post_count
@@ -114,6 +133,167 @@ impl Topic {
     pub fn post_count_mut(&mut self) -> &mut usize {
         &mut self.post_count
     }
+
+    /// Returns whether the topic no longer accepts replies.
+    #[inline]
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    #[inline]
+    pub fn set_stopped(&mut self, stopped: bool) {
+        self.stopped = stopped;
+    }
+
+    /// Returns the default name of the topic that overrides
+    /// `BBS_NONAME_NAME`.
+    #[inline]
+    pub fn noname(&self) -> Option<&[u8]> {
+        self.noname.as_ref().map(|n| &**n)
+    }
+
+    #[inline]
+    pub fn set_noname(&mut self, noname: Option<Box<[u8]>>) {
+        self.noname = noname;
+    }
+}
+
+/// Extracts the default name of a topic from the body of its first post,
+/// in which a line of the form `!774NAME` sets the default name to `NAME`.
+pub fn noname_command(body: &[u8]) -> Option<&[u8]> {
+    const COMMAND: &[u8] = b"!774";
+
+    let mut rest = body;
+    loop {
+        // Lines are delimited by either a raw newline or `<br>`.
+        let end = (0..rest.len())
+            .find(|&i| b'\n' == rest[i] || rest[i..].starts_with(b"<br>"))
+            .unwrap_or(rest.len());
+        let line = trim_spaces(&rest[..end]);
+        if line.starts_with(COMMAND) && line.len() > COMMAND.len() {
+            return Some(&line[COMMAND.len()..]);
+        }
+        if end == rest.len() {
+            return None;
+        }
+        rest = &rest[(end + if b'\n' == rest[end] { 1 } else { 4 })..];
+    }
+}
+
+/// Like `noname_command`, but takes the first line of a dat.
+pub fn noname_command_in_line(line: &[u8]) -> Option<Box<[u8]>> {
+    let line = if line.ends_with(b"\n") { &line[..(line.len()-1)] } else { line };
+    let record = dat::parse_line(line)?;
+    noname_command(&record.body).map(Into::into)
+}
+
+fn trim_spaces(mut s: &[u8]) -> &[u8] {
+    while let Some((&c, rest)) = s.split_first() {
+        if b' ' != c && b'\r' != c { break; }
+        s = rest;
+    }
+    while let Some((&c, rest)) = s.split_last() {
+        if b' ' != c && b'\r' != c { break; }
+        s = rest;
+    }
+    s
+}
+
+/// Makes the line appended to a topic that reached `max` posts, e.g.
+/// "１００１<><>Over 1000 Thread<> このスレッドは１０００を超えました。 ..."
+pub fn over_max_res_line(max: usize) -> Vec<u8> {
+    let mut line = Vec::with_capacity(192);
+    write_zenkaku_number(&mut line, max + 1);
+    write!(line, "<><>Over {} Thread<> ", max).unwrap();
+    line.extend_from_slice(THIS_TOPIC);
+    write_zenkaku_number(&mut line, max);
+    line.extend_from_slice(EXCEEDED);
+    line.extend_from_slice(b" <br>");
+    line.extend_from_slice(CREATE_NEW);
+    line.extend_from_slice(b" <>\n");
+    line
+}
+
+/// Makes the line appended to a topic whose dat reached `max_kb` KiB.
+pub fn over_max_size_line(max_kb: u64) -> Vec<u8> {
+    let mut line = Vec::with_capacity(192);
+    write!(line, "Over {kb}KB Thread<><>Over {kb}KB Thread<> ", kb=max_kb).unwrap();
+    line.extend_from_slice(THIS_TOPIC);
+    write!(line, "{}KB", max_kb).unwrap();
+    line.extend_from_slice(EXCEEDED);
+    line.extend_from_slice(b" <br>");
+    line.extend_from_slice(CREATE_NEW);
+    line.extend_from_slice(b" <>\n");
+    line
+}
+
+// "停止しました。。。"
+const STOPPED_NAME: &[u8] = b"\x92\xE2\x8E\x7E\x82\xB5\x82\xDC\x82\xB5\x82\xBD\x81\x42\x81\x42\x81\x42";
+
+/// Makes the line appended to a topic stopped by a moderator:
+/// "停止しました。。。<>停止<>停止<> 真・スレッドストッパー。。。(￣ー￣)ﾆﾔﾘｯ <>"
+pub fn stopped_line() -> Vec<u8> {
+    // "停止"
+    const STOPPED: &[u8] = b"\x92\xE2\x8E\x7E";
+    // "真・スレッドストッパー。。。(￣ー￣)ﾆﾔﾘｯ"
+    const STOPPER: &[u8] = b"\x90\x5E\x81\x45\x83\x58\x83\x8C\x83\x62\x83\x68\x83\x58\x83\x67\
+        \x83\x62\x83\x70\x81\x5B\x81\x42\x81\x42\x81\x42\x28\x81\x50\x81\x5B\x81\x50\x29\xC6\xD4\xD8\xAF";
+
+    let mut line = Vec::with_capacity(96);
+    line.extend_from_slice(STOPPED_NAME);
+    line.extend_from_slice(b"<>");
+    line.extend_from_slice(STOPPED);
+    line.extend_from_slice(b"<>");
+    line.extend_from_slice(STOPPED);
+    line.extend_from_slice(b"<> ");
+    line.extend_from_slice(STOPPER);
+    line.extend_from_slice(b" <>\n");
+    line
+}
+
+/// Makes the line that replaces a post deleted by a moderator:
+/// "あぼーん<>あぼーん<>あぼーん<>あぼーん<>TITLE", where `title` is only
+/// given for the first post. The body lacks the usual surrounding spaces as
+/// in 2channel.
+pub fn deleted_line(title: &[u8]) -> Vec<u8> {
+    // "あぼーん"
+    const ABORN: &[u8] = b"\x82\xA0\x82\xDA\x81\x5B\x82\xF1";
+
+    let mut line = Vec::with_capacity(4 * (ABORN.len() + 2) + title.len() + 1);
+    for _ in 0..4 {
+        line.extend_from_slice(ABORN);
+        line.extend_from_slice(b"<>");
+    }
+    line.extend_from_slice(title);
+    line.push(b'\n');
+    line
+}
+
+/// Returns whether a dat line is the one made by `over_max_res_line` or
+/// `over_max_size_line`.
+pub fn is_over_limit_line(line: &[u8]) -> bool {
+    dat::parse_line(line).map_or(false, |r| {
+        r.datetime.starts_with(b"Over ") && r.datetime.ends_with(b" Thread")
+    })
+}
+
+/// Returns whether a dat line, with or without the trailing newline, is the
+/// one made by `stopped_line`. The whole line is compared since anyone can
+/// post under the name.
+pub fn is_stopped_line(line: &[u8]) -> bool {
+    let line = if line.ends_with(b"\n") { &line[..(line.len()-1)] } else { line };
+    let stopped = stopped_line();
+    *line == stopped[..(stopped.len()-1)]
+}
+
+fn write_zenkaku_number(buf: &mut Vec<u8>, n: usize) {
+    let start = buf.len();
+    write!(buf, "{}", n).unwrap();
+    let digits: Vec<u8> = buf.drain(start..).collect();
+    for d in digits {
+        // "０" is 0x824F in Shift_JIS and the rest follows in order.
+        buf.extend_from_slice(&[0x82, 0x4F + (d - b'0')]);
+    }
 }
 
 #[cfg(test)]
@@ -123,12 +303,49 @@ mod tests {
     #[test]
     fn load() {
         // http://rio2016.5ch.net/test/read.cgi/dejima/1351397527/
-        let t = Topic::load(b"\
+        let t = Topic::load(1351397527, &b"\
             maji<>sage<>2012/10/28(Sun) 13:12:07.97 ID:nXycV/Aa0<>('A') ...<>('A')\n\
             maji<>sage<>2012/10/28(Sun) 15:15:21.32 ID:nXycV/Aa0<>('A' ) ...<>\n\
-            !softbank221044009121.bbtec.net<>sage<>2012/10/29(Mon) 17:44:00.15 ID:dJX3cXbx0<>a\n"
-        );
-        assert_eq!(b"('A')", t.title);
-        assert_eq!(3, t.post_count.read().unwrap());
+            !softbank221044009121.bbtec.net<>sage<>2012/10/29(Mon) 17:44:00.15 ID:dJX3cXbx0<>a\n"[..]
+        ).unwrap();
+        assert_eq!(b"('A')", &*t.title);
+        assert_eq!(3, t.post_count);
+    }
+
+    #[test]
+    fn over_limit_lines() {
+        let line = over_max_res_line(1000);
+        assert!(is_over_limit_line(&line[..(line.len()-1)]));
+        let line = over_max_size_line(512);
+        assert!(is_over_limit_line(&line[..(line.len()-1)]));
+        let line = stopped_line();
+        assert!(! is_over_limit_line(&line[..(line.len()-1)]));
+    }
+
+    #[test]
+    fn stopped_line_parses() {
+        let line = stopped_line();
+        let record = dat::parse_line(&line[..(line.len()-1)]).unwrap();
+        assert_eq!(record.mail, record.datetime);
+        assert!(record.title.is_empty());
+        assert!(is_stopped_line(&line));
+        assert!(is_stopped_line(&line[..(line.len()-1)]));
+    }
+
+    #[test]
+    fn stopped_name() {
+        // A post under the name of the stop line does not stop the topic.
+        let mut line = STOPPED_NAME.to_vec();
+        line.extend_from_slice(b"<>sage<>2018/01/01(Mon) 00:00:00.00<> a <>\n");
+        assert!(! is_stopped_line(&line));
+    }
+
+    #[test]
+    fn deleted_line_parses() {
+        let line = deleted_line(b"title");
+        let record = dat::parse_line(&line[..(line.len()-1)]).unwrap();
+        assert_eq!(record.name, record.body);
+        assert!(record.unspaced);
+        assert_eq!(b"title", &*record.title);
     }
 }
@@ -0,0 +1,113 @@
+//! Transcoding between Shift_JIS, the encoding of dats, and the others.
+
+use std::borrow::Cow;
+
+use encoding_rs::{EncoderResult, Encoding, SHIFT_JIS};
+
+/// Transcodes `src` encoded in `charset` into Shift_JIS.
+///
+/// If `charset` is `None`, `src` is taken as UTF-8 if it is valid UTF-8,
+/// or as Shift_JIS otherwise. UTF-8 is tried first since text in UTF-8 is
+/// often valid Shift_JIS as well, e.g. "ち" (`E3 81 A1`) reads as "縺｡",
+/// while Japanese text in Shift_JIS is hardly ever valid UTF-8.
+///
+/// Characters that Shift_JIS cannot represent are written as numeric
+/// character references if `pass` is `true` (`BBS_UNICODE=pass`) and
+/// replaced with `?` otherwise.
+///
+/// Returns `None` if `src` contains a malformed byte sequence.
+pub fn to_shift_jis<'a>(src: Cow<'a, [u8]>, charset: Option<&'static Encoding>, pass: bool)
+    -> Option<Cow<'a, [u8]>>
+{
+    if src.is_ascii() && charset.map_or(true, Encoding::is_ascii_compatible) {
+        return Some(src);
+    }
+
+    let text = match charset {
+        Some(enc) if enc != SHIFT_JIS => {
+            enc.decode_without_bom_handling_and_without_replacement(&src)?
+        },
+        // Valid UTF-8 is borrowed as it is.
+        None if ::std::str::from_utf8(&src).is_ok() => String::from_utf8_lossy(&src),
+        _ => {
+            SHIFT_JIS.decode_without_bom_handling_and_without_replacement(&src)?;
+            // Keep the original bytes rather than a round trip, which may
+            // turn NEC/IBM extension characters into their duplicates.
+            return Some(src);
+        },
+    };
+
+    Some(encode(&text, pass).into())
+}
+
+/// Decodes Shift_JIS text from a dat, replacing malformed sequences with
+/// U+FFFD.
+pub fn from_shift_jis(src: &[u8]) -> Cow<str> {
+    SHIFT_JIS.decode_without_bom_handling(src).0
+}
+
+fn encode(text: &str, pass: bool) -> Vec<u8> {
+    if pass {
+        return SHIFT_JIS.encode(text).0.into_owned();
+    }
+
+    let mut encoder = SHIFT_JIS.new_encoder();
+    let mut ret = Vec::with_capacity(text.len());
+    let mut text = text;
+    loop {
+        if let Some(n) = encoder.max_buffer_length_from_utf8_without_replacement(text.len()) {
+            ret.reserve(n);
+        }
+        let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(text, &mut ret, true);
+        text = &text[read..];
+        match result {
+            EncoderResult::InputEmpty => return ret,
+            EncoderResult::OutputFull => {},
+            EncoderResult::Unmappable(_) => ret.push(b'?'),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::UTF_8;
+
+    use super::*;
+
+    #[test]
+    fn shift_jis() {
+        // "あ"
+        let sjis: &[u8] = b"\x82\xA0";
+        assert_eq!(Some(sjis), to_shift_jis(sjis.into(), None, true).as_ref().map(|c| &**c));
+        assert_eq!(Some(sjis), to_shift_jis(sjis.into(), Some(SHIFT_JIS), true).as_ref().map(|c| &**c));
+        assert!(to_shift_jis((b"\x82" as &[u8]).into(), Some(SHIFT_JIS), true).is_none());
+        assert_eq!("\u{3042}", from_shift_jis(sjis));
+    }
+
+    #[test]
+    fn utf8() {
+        // "あ😀"
+        let utf8: &[u8] = b"\xE3\x81\x82\xF0\x9F\x98\x80";
+        assert_eq!(
+            Some(b"\x82\xA0&#128512;" as &[u8]),
+            to_shift_jis(utf8.into(), Some(UTF_8), true).as_ref().map(|c| &**c),
+        );
+        assert_eq!(
+            Some(b"\x82\xA0?" as &[u8]),
+            to_shift_jis(utf8.into(), Some(UTF_8), false).as_ref().map(|c| &**c),
+        );
+        assert!(to_shift_jis((b"\xE3\x81" as &[u8]).into(), Some(UTF_8), true).is_none());
+    }
+
+    #[test]
+    fn ambiguous() {
+        // "ち" in UTF-8, which is also "縺｡" in Shift_JIS.
+        let utf8: &[u8] = b"\xE3\x81\xA1";
+        assert_eq!(
+            Some(b"\x82\xBF" as &[u8]),
+            to_shift_jis(utf8.into(), None, true).as_ref().map(|c| &**c),
+        );
+        // Taken as Shift_JIS if so specified.
+        assert_eq!(Some(utf8), to_shift_jis(utf8.into(), Some(SHIFT_JIS), true).as_ref().map(|c| &**c));
+    }
+}
@@ -0,0 +1,240 @@
+//! Routes for administrators authenticated with API tokens of the `admin`
+//! role, see `middleware::token`. They are mounted apart from the rest of
+//! the API, e.g. at `/admin`.
+//!
+//! An admin token may only manage the boards it is allowed to post to,
+//! which includes moderating their topics.
+
+use std::io::{self, Read};
+
+use rocket::Data;
+use rocket::http::{ContentType, Status};
+use rocket::outcome::Outcome::*;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::Content;
+use rocket::response::status::Custom;
+use serde_json;
+
+use super::super::{BoardId, Key};
+use super::error;
+use super::post::{field, Bearer, Response};
+use bbs::{Bbs, BoardRef};
+use middleware::token::Token;
+use setting;
+
+/// A bot authenticated with an admin token.
+pub struct Admin<'r> {
+    token: &'r Token,
+}
+
+#[derive(Deserialize)]
+pub struct NewBoard {
+    id: String,
+    /// The board whose SETTING.TXT is copied to the new board. The new board
+    /// starts with an empty SETTING.TXT if absent.
+    template: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewTitle {
+    title: String,
+}
+
+#[derive(Serialize)]
+struct Done<'a> {
+    board: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<usize>,
+}
+
+const BODY_LIMIT: u64 = 4 * 1024;
+
+/// Creates a board.
+#[post("/boards", format = "application/json", data = "<data>")]
+pub fn create_board(data: Data, bbs: &Bbs, admin: Admin) -> Response {
+    let mut buf = Vec::new();
+    data.open().take(BODY_LIMIT).read_to_end(&mut buf)
+        .map_err(|_| error(Status::BadRequest, "Failed to read the request body"))?;
+    let new: NewBoard = serde_json::from_slice(&buf)
+        .map_err(|_| error(Status::BadRequest, "Malformed JSON"))?;
+    if ! admin.token.allows(&new.id) {
+        return Err(error(Status::Forbidden, "The token is not allowed to manage this board"));
+    }
+
+    let setting_txt = match new.template {
+        Some(ref template) => {
+            let brd = bbs.board(template)
+                .ok_or_else(|| error(Status::NotFound, "Template board not found"))?;
+            brd.settings().as_ref().to_vec()
+        },
+        None => Vec::new(),
+    };
+    bbs.create_board(&new.id, &setting_txt).map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidInput => error(Status::BadRequest, "Invalid board id"),
+        io::ErrorKind::AlreadyExists => error(Status::Conflict, "The board already exists"),
+        _ => {
+            error!("failed to create a board, {}: {:?}", new.id, e);
+            error(Status::InternalServerError, "Failed to create the board")
+        },
+    })?;
+    info!("{} created a board, {}", admin.token.name(), new.id);
+    done(Done { board: &new.id, key: None, number: None })
+}
+
+/// Makes a board read-only.
+#[post("/boards/<board>/retire")]
+pub fn retire_board(board: BoardId, bbs: &Bbs, admin: Admin) -> Response {
+    allow(&admin, &board)?;
+    match bbs.retire_board(&board) {
+        Ok(true) => {
+            info!("{} retired a board, {}", admin.token.name(), &*board);
+            done(Done { board: &board, key: None, number: None })
+        },
+        Ok(false) => Err(error(Status::NotFound, "Board not found")),
+        Err(e) => {
+            error!("failed to retire a board, {}: {:?}", &*board, e);
+            Err(error(Status::InternalServerError, "Failed to retire the board"))
+        },
+    }
+}
+
+/// Removes a board, moving its directory aside.
+#[delete("/boards/<board>")]
+pub fn remove_board(board: BoardId, bbs: &Bbs, admin: Admin) -> Response {
+    allow(&admin, &board)?;
+    match bbs.remove_board(&board) {
+        Ok(true) => {
+            info!("{} removed a board, {}", admin.token.name(), &*board);
+            done(Done { board: &board, key: None, number: None })
+        },
+        Ok(false) => Err(error(Status::NotFound, "Board not found")),
+        Err(e) => {
+            error!("failed to remove a board, {}: {:?}", &*board, e);
+            Err(error(Status::InternalServerError, "Failed to remove the board"))
+        },
+    }
+}
+
+/// Stops a topic, appending the stop line to its dat.
+#[post("/boards/<board>/topics/<key>/stop")]
+pub fn stop_topic(board: BoardId, key: Key, bbs: &Bbs, admin: Admin) -> Response {
+    let brd = moderated(&admin, &board, bbs)?;
+    match brd.stop_topic(key.number) {
+        Ok(true) => {
+            info!("{} stopped a topic, {}/{}", admin.token.name(), brd.id(), &*key);
+            done(Done { board: brd.id(), key: Some(key.number), number: None })
+        },
+        Ok(false) if brd.topic(key.number).is_some() => {
+            Err(error(Status::Conflict, "The thread is already stopped"))
+        },
+        Ok(false) => Err(error(Status::NotFound, "Thread not found")),
+        Err(e) => {
+            error!("failed to stop a topic, {}/{}: {:?}", brd.id(), &*key, e);
+            Err(error(Status::InternalServerError, "Failed to stop the thread"))
+        },
+    }
+}
+
+/// Replaces a post with the "あぼーん" line, keeping the numbering.
+#[delete("/boards/<board>/topics/<key>/posts/<number>")]
+pub fn delete_post(board: BoardId, key: Key, number: Key, bbs: &Bbs, admin: Admin) -> Response {
+    let brd = moderated(&admin, &board, bbs)?;
+    let number = number.number as usize;
+    match brd.delete_post(key.number, number) {
+        Ok(true) => {
+            info!("{} deleted a post, {}/{}/{}", admin.token.name(), brd.id(), &*key, number);
+            done(Done { board: brd.id(), key: Some(key.number), number: Some(number) })
+        },
+        Ok(false) => Err(error(Status::NotFound, "Post not found")),
+        Err(ref e) if io::ErrorKind::InvalidInput == e.kind() => {
+            Err(error(Status::Conflict, "The stop line cannot be deleted"))
+        },
+        Err(e) => {
+            error!("failed to delete a post, {}/{}/{}: {:?}", brd.id(), &*key, number, e);
+            Err(error(Status::InternalServerError, "Failed to delete the post"))
+        },
+    }
+}
+
+/// Deletes a topic and its dat.
+#[delete("/boards/<board>/topics/<key>")]
+pub fn delete_topic(board: BoardId, key: Key, bbs: &Bbs, admin: Admin) -> Response {
+    let brd = moderated(&admin, &board, bbs)?;
+    match brd.delete_topic(key.number) {
+        Ok(true) => {
+            info!("{} deleted a topic, {}/{}", admin.token.name(), brd.id(), &*key);
+            done(Done { board: brd.id(), key: Some(key.number), number: None })
+        },
+        Ok(false) => Err(error(Status::NotFound, "Thread not found")),
+        Err(e) => {
+            error!("failed to delete a topic, {}/{}: {:?}", brd.id(), &*key, e);
+            Err(error(Status::InternalServerError, "Failed to delete the thread"))
+        },
+    }
+}
+
+/// Replaces the title of a topic with `title` in the UTF-8 JSON body.
+#[put("/boards/<board>/topics/<key>/title", format = "application/json", data = "<data>")]
+pub fn edit_title(board: BoardId, key: Key, data: Data, bbs: &Bbs, admin: Admin) -> Response {
+    let brd = moderated(&admin, &board, bbs)?;
+    let mut buf = Vec::new();
+    data.open().take(BODY_LIMIT).read_to_end(&mut buf)
+        .map_err(|_| error(Status::BadRequest, "Failed to read the request body"))?;
+    let new: NewTitle = serde_json::from_slice(&buf)
+        .map_err(|_| error(Status::BadRequest, "Malformed JSON"))?;
+    let pass = brd.settings().get::<setting::common::Unicode>().cloned().unwrap_or(true);
+    let title = field(&new.title, false, pass);
+    if title.is_empty() {
+        return Err(error(Status::BadRequest, "Empty title"));
+    }
+
+    match brd.edit_title(key.number, title) {
+        Ok(true) => {
+            info!("{} edited the title of a topic, {}/{}", admin.token.name(), brd.id(), &*key);
+            done(Done { board: brd.id(), key: Some(key.number), number: None })
+        },
+        Ok(false) => Err(error(Status::NotFound, "Thread not found")),
+        Err(e) => {
+            error!("failed to edit the title of a topic, {}/{}: {:?}", brd.id(), &*key, e);
+            Err(error(Status::InternalServerError, "Failed to edit the title"))
+        },
+    }
+}
+
+/// Looks up a board the token of `admin` may moderate.
+fn moderated<'b>(admin: &Admin, board: &str, bbs: &'b Bbs)
+    -> Result<BoardRef<'b>, Custom<Content<String>>>
+{
+    allow(admin, board)?;
+    bbs.board(board).ok_or_else(|| error(Status::NotFound, "Board not found"))
+}
+
+fn allow(admin: &Admin, board: &str) -> Result<(), Custom<Content<String>>> {
+    if admin.token.allows(board) {
+        Ok(())
+    } else {
+        Err(error(Status::Forbidden, "The token is not allowed to manage this board"))
+    }
+}
+
+fn done(done: Done) -> Response {
+    let body = serde_json::to_string(&done).expect("failed to serialize");
+    Ok(Content(ContentType::JSON, body))
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin<'r> {
+    type Error = ();
+
+    fn from_request(req: &'a Request<'r>) -> Outcome<Self, ()> {
+        match req.guard::<Bearer>() {
+            Success(ref bearer) if bearer.token().is_admin() => {
+                Success(Admin { token: bearer.token() })
+            },
+            Success(_) => Failure((Status::Forbidden, ())),
+            Failure(f) => Failure(f),
+            Forward(f) => Forward(f),
+        }
+    }
+}
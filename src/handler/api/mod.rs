@@ -0,0 +1,163 @@
+//! A read-only JSON API for bots and apps, which serves subject.txt and
+//! dats parsed and decoded into UTF-8.
+//!
+//! Bodies are left as in the dat, i.e. HTML with lines delimited by `<br>`.
+//!
+//! Bots may also post with API tokens, see `post`, and administrators may
+//! manage boards, see `admin`.
+
+pub mod admin;
+pub mod post;
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use rocket::http::{ContentType, RawStr, Status};
+use rocket::request::FromParam;
+use rocket::response::content::Content;
+use rocket::response::status::Custom;
+use serde_json;
+
+use super::BoardId;
+use bbs::{Bbs, BoardRef};
+use bbs::board::{Dat, SubjectJson};
+use bbs::dat::{self, Record};
+use encoding::from_shift_jis;
+use responder::Cacheable;
+
+pub type TopicJson = Cacheable<Vec<u8>>;
+
+#[derive(FromForm)]
+pub struct Since {
+    since: usize,
+}
+
+/// The body of an error response, e.g. `{"error":"Board not found"}`.
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+/// A key followed by `.json`.
+pub struct Json(pub u64);
+
+#[derive(Serialize)]
+struct Topic<'a> {
+    key: u64,
+    title: Cow<'a, str>,
+    post_count: usize,
+    posts: Vec<Post<'a>>,
+}
+
+#[derive(Serialize)]
+struct Post<'a> {
+    number: usize,
+    name: Cow<'a, str>,
+    mail: Cow<'a, str>,
+    datetime: Cow<'a, str>,
+    id: Option<Cow<'a, str>>,
+    body: Cow<'a, str>,
+}
+
+#[get("/<board>/subject.json")]
+pub fn subject(board: BoardId, bbs: &Bbs)
+    -> Result<Content<Arc<SubjectJson>>, Custom<Content<String>>>
+{
+    let brd = bbs.board(&*board).ok_or_else(|| error(Status::NotFound, "Board not found"))?;
+    Ok(Content(ContentType::JSON, brd.subject_json()))
+}
+
+#[get("/<board>/<key>", rank = 2)]
+pub fn topic(board: BoardId, key: Json, bbs: &Bbs)
+    -> Result<Content<Arc<TopicJson>>, Custom<Content<String>>>
+{
+    serve(board, key.0, 0, bbs)
+}
+
+/// The posts after the `since`-th post, e.g. `/api/<board>/<key>.json?since=100`.
+#[get("/<board>/<key>?<query>", rank = 1)]
+pub fn topic_since(board: BoardId, key: Json, query: Since, bbs: &Bbs)
+    -> Result<Content<Arc<TopicJson>>, Custom<Content<String>>>
+{
+    serve(board, key.0, query.since, bbs)
+}
+
+fn serve(board: BoardId, key: u64, since: usize, bbs: &Bbs)
+    -> Result<Content<Arc<TopicJson>>, Custom<Content<String>>>
+{
+    let brd = bbs.board(&*board).ok_or_else(|| error(Status::NotFound, "Board not found"))?;
+    // Archived topics are served as well.
+    let dat = match brd.topic(key).map(|t| t.dat()) {
+        Some(dat) => dat,
+        None => read_kako(&brd, key),
+    };
+    match dat {
+        Ok(dat) => Ok(Content(ContentType::JSON, Arc::new(render(key, &dat, since)))),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(error(Status::NotFound, "Thread not found"))
+        },
+        Err(_) => Err(error(Status::InternalServerError, "Failed to read the dat")),
+    }
+}
+
+fn read_kako(brd: &BoardRef, key: u64) -> io::Result<Dat> {
+    let mut f = File::open(brd.kako_path(key))?;
+    let m = f.metadata()?;
+    let mut buf = Vec::with_capacity(m.len() as usize);
+    f.read_to_end(&mut buf)?;
+    Ok(Cacheable::new(buf, (&m).into()))
+}
+
+/// Converts a dat into JSON, omitting the lines that cannot be parsed.
+fn render(key: u64, dat: &Dat, since: usize) -> TopicJson {
+    // Reading from a slice never fails.
+    let records: Vec<Option<Record>> = dat::Reader::new(&dat[..])
+        .map(|r| r.unwrap().ok())
+        .collect();
+    let title = records.first()
+        .and_then(Option::as_ref)
+        .map_or(&b""[..], |r| &*r.title);
+
+    let posts = records.iter()
+        .enumerate()
+        .skip(since)
+        .filter_map(|(i, r)| r.as_ref().map(|r| Post {
+            number: i + 1,
+            name: from_shift_jis(&r.name),
+            mail: from_shift_jis(&r.mail),
+            datetime: from_shift_jis(&r.datetime),
+            id: r.id.as_ref().map(|id| from_shift_jis(id)),
+            body: from_shift_jis(&r.body),
+        }))
+        .collect();
+    let topic = Topic {
+        key,
+        title: from_shift_jis(title),
+        post_count: records.len(),
+        posts,
+    };
+
+    let json = serde_json::to_vec(&topic).expect("failed to serialize a topic");
+    // The ETag differs for each `since` while the mtime follows the dat.
+    Cacheable::new(json, dat.metadata().variant(since as u64))
+}
+
+/// Makes an error response in JSON.
+fn error(status: Status, message: &str) -> Custom<Content<String>> {
+    let body = serde_json::to_string(&ErrorBody { error: message }).expect("failed to serialize");
+    Custom(status, Content(ContentType::JSON, body))
+}
+
+impl<'a> FromParam<'a> for Json {
+    type Error = ();
+
+    fn from_param(p: &'a RawStr) -> Result<Self, ()> {
+        if p.ends_with(".json") {
+            p[..(p.len()-5)].parse().map(Json).map_err(|_| ())
+        } else {
+            Err(())
+        }
+    }
+}
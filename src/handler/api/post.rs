@@ -0,0 +1,147 @@
+//! Posting through the JSON API, for bots authenticated with API tokens.
+//!
+//! The request body is a UTF-8 JSON object with `name`, `mail` and `body`,
+//! plus `title` when creating a topic. The token is sent in the header
+//! `Authorization: Bearer <token>`. Posting is disabled unless the store of
+//! `Tokens` is managed by Rocket.
+
+use std::io::Read;
+
+use encoding_rs::UTF_8;
+use rocket::Data;
+use rocket::http::{ContentType, Status};
+use rocket::outcome::Outcome::*;
+use rocket::request::{FromRequest, Outcome, Request, State};
+use rocket::response::content::Content;
+use rocket::response::status::Custom;
+use serde_json;
+
+use super::error;
+use super::super::BoardId;
+use super::super::submit::submit;
+use bbs::Bbs;
+use encoding::{from_shift_jis, to_shift_jis};
+use middleware::token::{Token, Tokens};
+use post::Post;
+use setting;
+use validator::{html_escape, Digits};
+
+/// A bot authenticated with an API token.
+pub struct Bearer<'a, 'r: 'a> {
+    token: &'r Token,
+    tokens: &'r Tokens,
+    req: &'a Request<'r>,
+}
+
+#[derive(Deserialize)]
+pub struct NewPost {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    mail: String,
+    body: String,
+    title: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Posted {
+    key: u64,
+    number: usize,
+}
+
+pub type Response = Result<Content<String>, Custom<Content<String>>>;
+
+const BODY_LIMIT: u64 = 64 * 1024;
+
+/// Posts to the topic `key`.
+#[post("/<board>/<key>", format = "application/json", data = "<data>")]
+pub fn reply<'a, 'r>(
+    board: BoardId<'r>, key: Digits<'r>, data: Data, bbs: &'r Bbs, bearer: Bearer<'a, 'r>,
+) -> Response
+{
+    serve(board, Some(key), data, bbs, bearer)
+}
+
+/// Creates a topic.
+#[post("/<board>", format = "application/json", data = "<data>")]
+pub fn create<'a, 'r>(board: BoardId<'r>, data: Data, bbs: &'r Bbs, bearer: Bearer<'a, 'r>)
+    -> Response
+{
+    serve(board, None, data, bbs, bearer)
+}
+
+fn serve<'a, 'r>(
+    board: BoardId<'r>, key: Option<Digits<'r>>, data: Data, bbs: &'r Bbs, bearer: Bearer<'a, 'r>,
+) -> Response
+{
+    let brd = bbs.board(&*board).ok_or_else(|| error(Status::NotFound, "Board not found"))?;
+    if ! bearer.token.allows(brd.id()) {
+        return Err(error(Status::Forbidden, "The token is not allowed to post to this board"));
+    }
+    let mut buf = Vec::new();
+    data.open().take(BODY_LIMIT).read_to_end(&mut buf)
+        .map_err(|_| error(Status::BadRequest, "Failed to read the request body"))?;
+    let new: NewPost = serde_json::from_slice(&buf)
+        .map_err(|_| error(Status::BadRequest, "Malformed JSON"))?;
+
+    let pass = brd.settings().get::<setting::common::Unicode>().cloned().unwrap_or(true);
+    let name = field(&new.name, false, pass);
+    let mail = field(&new.mail, false, pass);
+    let body = field(&new.body, true, pass);
+    let title = new.title.as_ref().map(|t| field(t, false, pass).into());
+    let mut post = Post::new(name, mail, body, title);
+
+    if ! bearer.tokens.reserve(bearer.token) {
+        return Err(error(Status::TooManyRequests, "The quota of the token is exhausted"));
+    }
+    let submitted = submit(bbs, &brd, board, key, &mut post, Some(bearer.token), bearer.req);
+    let submitted = submitted.map_err(|e| {
+        bearer.tokens.release(bearer.token);
+        let status = e.status();
+        error(status, &from_shift_jis(&e.into_message()))
+    })?;
+    info!("{} posted to {}/{}", bearer.token.name(), brd.id(), submitted.key);
+
+    let posted = Posted { key: submitted.key, number: submitted.number };
+    Ok(Content(ContentType::JSON, serde_json::to_string(&posted).expect("failed to serialize")))
+}
+
+/// Converts a UTF-8 field into the form of a dat field, i.e. HTML-escaped
+/// Shift_JIS, turning newlines into `<br>` if `multiline` and removing them
+/// otherwise.
+pub(super) fn field(src: &str, multiline: bool, pass: bool) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(src.len());
+    for (i, line) in src.lines().enumerate() {
+        if 0 < i && multiline {
+            escaped.extend_from_slice(b" <br> ");
+        }
+        escaped.extend_from_slice(&html_escape(line.as_bytes().to_vec()));
+    }
+    to_shift_jis(escaped.into(), Some(UTF_8), pass)
+        .expect("a `String` is valid UTF-8")
+        .into_owned()
+}
+
+impl<'a, 'r> Bearer<'a, 'r> {
+    pub fn token(&self) -> &'r Token {
+        self.token
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Bearer<'a, 'r> {
+    type Error = ();
+
+    fn from_request(req: &'a Request<'r>) -> Outcome<Self, ()> {
+        let tokens = match req.guard::<State<Tokens>>() {
+            Success(tokens) => tokens.inner(),
+            _ => return Failure((Status::NotFound, ())),
+        };
+        let token = req.headers().get_one("Authorization")
+            .filter(|h| h.starts_with("Bearer "))
+            .and_then(|h| tokens.authenticate(h["Bearer ".len()..].trim()));
+        match token {
+            Some(token) => Success(Bearer { token, tokens, req }),
+            None => Failure((Status::Unauthorized, ())),
+        }
+    }
+}
@@ -1,14 +1,44 @@
-use rocket::http::RawStr;
-use rocket::request::FromParam;
+use std::io;
+use std::sync::Arc;
 
-use super::super::BoardId;
+use rocket::http::{RawStr, Status};
+use rocket::request::{FromParam, Request};
+use rocket::response::{Redirect, Responder};
+use rocket::response::status::Custom;
 
-#[get("/<_board>/dat/<_dat>")]
-pub fn get(_board: BoardId, _dat: Dat) {
-    unimplemented!();
+use super::super::{BoardId, BOARD_NOT_FOUND, TOPIC_NOT_FOUND};
+use bbs::Bbs;
+use bbs::board;
+
+pub enum DatResponse {
+    Dat(Arc<board::Dat>),
+    /// The topic has been moved to the archive (_dat-ochi_), which 2channel
+    /// browsers recognize by the `302 Found` status.
+    Archived(Redirect),
+}
+
+#[get("/<board>/dat/<dat>")]
+pub fn get(board: BoardId, dat: Dat, bbs: &Bbs)
+    -> Result<DatResponse, Custom<&'static str>>
+{
+    let brd = bbs.board(&*board).ok_or(BOARD_NOT_FOUND)?;
+    let topic = match brd.topic(dat.0) {
+        Some(t) => t,
+        None => return if brd.kako_path(dat.0).is_file() {
+            let url = format!("/{}/{}", &*board, board::kako_path(dat.0));
+            Ok(DatResponse::Archived(Redirect::found(&url)))
+        } else {
+            Err(TOPIC_NOT_FOUND)
+        },
+    };
+    match topic.dat() {
+        Ok(dat) => Ok(DatResponse::Dat(Arc::new(dat))),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(TOPIC_NOT_FOUND),
+        Err(_) => Err(Custom(Status::InternalServerError, "Failed to read the dat")),
+    }
 }
 
-pub struct Dat(usize);
+pub struct Dat(pub u64);
 
 impl<'a> FromParam<'a> for Dat {
     type Error = ();
@@ -21,3 +51,12 @@ impl<'a> FromParam<'a> for Dat {
         }
     }
 }
+
+impl<'r> Responder<'r> for DatResponse {
+    fn respond_to(self, req: &Request) -> Result<::rocket::Response<'r>, Status> {
+        match self {
+            DatResponse::Dat(dat) => dat.respond_to(req),
+            DatResponse::Archived(r) => r.respond_to(req),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use rocket::http::{RawStr, Status};
+use rocket::response::status::Custom;
+
+use super::dat::Dat;
+use super::super::{BoardId, BOARD_NOT_FOUND, TOPIC_NOT_FOUND};
+use bbs::Bbs;
+use bbs::board;
+use responder::Cacheable;
+
+/// An archived dat, e.g. `/<board>/kako/1234/12345/1234567890.dat`.
+#[get("/<board>/kako/<dir1>/<dir2>/<dat>")]
+pub fn get(board: BoardId, dir1: &RawStr, dir2: &RawStr, dat: Dat, bbs: &Bbs)
+    -> Result<Arc<board::Dat>, Custom<&'static str>>
+{
+    serve(board, &format!("kako/{}/{}/{}.dat", dir1.as_str(), dir2.as_str(), dat.0), dat, bbs)
+}
+
+/// An archived dat with a key shorter than 10 digits,
+/// e.g. `/<board>/kako/123/123456789.dat`.
+#[get("/<board>/kako/<dir>/<dat>")]
+pub fn get_short(board: BoardId, dir: &RawStr, dat: Dat, bbs: &Bbs)
+    -> Result<Arc<board::Dat>, Custom<&'static str>>
+{
+    serve(board, &format!("kako/{}/{}.dat", dir.as_str(), dat.0), dat, bbs)
+}
+
+fn serve(board: BoardId, requested: &str, dat: Dat, bbs: &Bbs)
+    -> Result<Arc<board::Dat>, Custom<&'static str>>
+{
+    let brd = bbs.board(&*board).ok_or(BOARD_NOT_FOUND)?;
+    if requested != board::kako_path(dat.0) {
+        return Err(TOPIC_NOT_FOUND);
+    }
+
+    let read = File::open(brd.kako_path(dat.0)).and_then(|mut f| {
+        let m = f.metadata()?;
+        let mut buf = Vec::with_capacity(m.len() as usize);
+        f.read_to_end(&mut buf)?;
+        Ok(Cacheable::new(buf, (&m).into()))
+    });
+    match read {
+        Ok(dat) => Ok(Arc::new(dat)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(TOPIC_NOT_FOUND),
+        Err(_) => Err(Custom(Status::InternalServerError, "Failed to read the dat")),
+    }
+}
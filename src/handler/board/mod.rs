@@ -1,10 +1,21 @@
 pub mod dat;
+pub mod kako;
 pub mod setting_txt;
 pub mod subject_txt;
 
-use super::BoardId;
+use std::sync::Arc;
 
-#[get("/<_board>")]
-pub fn get(_board: BoardId) {
-    unimplemented!();
+use rocket::response::content::Content;
+use rocket::response::status::Custom;
+
+use super::{BoardId, BOARD_NOT_FOUND, shift_jis_html};
+use bbs::Bbs;
+use bbs::board::IndexHtml;
+
+#[get("/<board>")]
+pub fn get<'r>(board: BoardId, bbs: &'r Bbs)
+    -> Result<Content<Arc<IndexHtml>>, Custom<&'static str>>
+{
+    let brd = bbs.board(&*board).ok_or(BOARD_NOT_FOUND)?;
+    Ok(Content(shift_jis_html(), brd.index_html()))
 }
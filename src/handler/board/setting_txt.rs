@@ -1,13 +1,17 @@
+use std::sync::Arc;
+
 use rocket::response::status::Custom;
 
 use super::super::{BoardId, BOARD_NOT_FOUND};
 use bbs::Bbs;
-use setting::Settings;
+use responder::Cacheable;
 
+/// Serves SETTING.TXT as it is currently loaded, whose ETag changes when it
+/// is reloaded.
 #[get("/<board>/SETTING.TXT")]
 pub fn get<'r>(board: BoardId, bbs: &'r Bbs)
-    -> Result<&'r Settings, Custom<&'static str>>
+    -> Result<Arc<Cacheable<Box<[u8]>>>, Custom<&'static str>>
 {
     let brd = bbs.board(&*board).ok_or(BOARD_NOT_FOUND)?;
-    Ok(brd.settings())
+    Ok(brd.settings().text())
 }
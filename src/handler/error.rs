@@ -0,0 +1,108 @@
+//! The errors of posting, and the catchers rendering errors in the style of
+//! bbs.cgi.
+
+use std::borrow::Cow;
+use std::io;
+
+use rocket::http::Status;
+use rocket::request::Request;
+
+use super::test::bbs::Page;
+use middleware;
+
+/// The reason why a post has not been written.
+#[derive(Debug)]
+pub enum Error<'r> {
+    BoardNotFound,
+    TopicNotFound,
+    /// The post is rejected for its content with a Shift_JIS message.
+    Invalid(Cow<'r, [u8]>),
+    /// The poster is posting too fast or repeatedly, with a Shift_JIS
+    /// message.
+    Throttled(Cow<'r, [u8]>),
+    Stopped,
+    /// The board is closed by `BBS_HEISA`.
+    Closed,
+    Io(io::Error),
+    /// The poster has to confirm the post. bbs.cgi shows the confirmation
+    /// page rather than an error for this.
+    Confirm,
+}
+
+// "ＥＲＲＯＲ：該当する板は存在しません！"
+const BOARD_NOT_FOUND: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x8A\x59\x93\x96\x82\xB7\x82\xE9\x94\xC2\x82\xCD\x91\xB6\x8D\xDD\x82\xB5\x82\xDC\x82\xB9\x82\xF1\x81\x49";
+// "ＥＲＲＯＲ：該当するスレッドは存在しません！"
+const TOPIC_NOT_FOUND: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x8A\x59\x93\x96\x82\xB7\x82\xE9\x83\x58\x83\x8C\x83\x62\x83\x68\x82\xCD\
+    \x91\xB6\x8D\xDD\x82\xB5\x82\xDC\x82\xB9\x82\xF1\x81\x49";
+// "ＥＲＲＯＲ：このスレッドには書き込めません。"
+const STOPPED: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x82\xB1\x82\xCC\x83\x58\x83\x8C\x83\x62\x83\x68\x82\xC9\x82\xCD\
+    \x8F\x91\x82\xAB\x8D\x9E\x82\xDF\x82\xDC\x82\xB9\x82\xF1\x81\x42";
+// "ＥＲＲＯＲ：この板は閉鎖中です。"
+const HEISA: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x82\xB1\x82\xCC\x94\xC2\x82\xCD\x95\xC2\x8D\xBD\x92\x86\x82\xC5\x82\xB7\x81\x42";
+// "ＥＲＲＯＲ：サーバーで問題が発生しました。しばらくしてから再度お試しください。"
+const SERVER_ERROR: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x83\x54\x81\x5B\x83\x6F\x81\x5B\x82\xC5\x96\xE2\x91\xE8\x82\xAA\x94\xAD\x90\xB6\
+    \x82\xB5\x82\xDC\x82\xB5\x82\xBD\x81\x42\x82\xB5\x82\xCE\x82\xE7\x82\xAD\x82\xB5\x82\xC4\
+    \x82\xA9\x82\xE7\x8D\xC4\x93\x78\x82\xA8\x8E\x8E\x82\xB5\x82\xAD\x82\xBE\x82\xB3\x82\xA2\x81\x42";
+// "ＥＲＲＯＲ：書き込み確認が必要です。"
+const CONFIRM: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x8F\x91\x82\xAB\x8D\x9E\x82\xDD\x8A\x6D\x94\x46\x82\xAA\x95\x4B\x97\x76\x82\xC5\x82\xB7\x81\x42";
+// "ＥＲＲＯＲ：ページが見つかりません。"
+const NOT_FOUND: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x83\x79\x81\x5B\x83\x57\x82\xAA\x8C\xA9\x82\xC2\x82\xA9\x82\xE8\x82\xDC\x82\xB9\x82\xF1\x81\x42";
+
+impl<'r> Error<'r> {
+    pub fn status(&self) -> Status {
+        match *self {
+            Error::BoardNotFound | Error::TopicNotFound => Status::NotFound,
+            Error::Invalid(_) => Status::BadRequest,
+            Error::Throttled(_) => Status::TooManyRequests,
+            Error::Stopped | Error::Closed | Error::Confirm => Status::Forbidden,
+            Error::Io(_) => Status::InternalServerError,
+        }
+    }
+
+    /// Returns the HTML-escaped Shift_JIS message shown to the poster.
+    pub fn into_message(self) -> Cow<'r, [u8]> {
+        match self {
+            Error::BoardNotFound => BOARD_NOT_FOUND.into(),
+            Error::TopicNotFound => TOPIC_NOT_FOUND.into(),
+            Error::Invalid(message) | Error::Throttled(message) => message,
+            Error::Stopped => STOPPED.into(),
+            Error::Closed => HEISA.into(),
+            // The details are only logged.
+            Error::Io(_) => SERVER_ERROR.into(),
+            Error::Confirm => CONFIRM.into(),
+        }
+    }
+}
+
+impl<'r> From<middleware::Error<'r>> for Error<'r> {
+    fn from(e: middleware::Error<'r>) -> Self {
+        match e {
+            middleware::Error::Invalid(message) => Error::Invalid(message),
+            middleware::Error::Throttled(message) => Error::Throttled(message),
+            middleware::Error::Confirm => Error::Confirm,
+        }
+    }
+}
+
+impl<'r> From<io::Error> for Error<'r> {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[error(404)]
+pub fn not_found(_: &Request) -> Page<'static> {
+    Page::Error(Status::NotFound, NOT_FOUND.into())
+}
+
+#[error(500)]
+pub fn internal_error(_: &Request) -> Page<'static> {
+    Page::Error(Status::InternalServerError, SERVER_ERROR.into())
+}
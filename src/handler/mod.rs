@@ -1,11 +1,17 @@
-use rocket::http::Status;
+use rocket::http::{ContentType, Status};
 use rocket::response::status::Custom;
 
 use validator;
 
+pub mod api;
 pub mod board;
+pub mod error;
 pub mod test;
 
+mod submit;
+
+pub use self::error::Error;
+
 pub type BoardId<'a> = validator::AlphaNum<'a>;
 pub type Key<'a> = validator::Digits<'a>;
 
@@ -13,3 +19,12 @@ const BOARD_NOT_FOUND: Custom<&str> = Custom(
     Status::NotFound,
     "Board not found",
 );
+
+const TOPIC_NOT_FOUND: Custom<&str> = Custom(
+    Status::NotFound,
+    "Thread not found",
+);
+
+fn shift_jis_html() -> ContentType {
+    ContentType::with_params("text", "html", ("charset", "Shift_JIS"))
+}
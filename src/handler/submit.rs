@@ -0,0 +1,117 @@
+//! Writing a post to a dat, which is shared by bbs.cgi and the JSON API.
+
+use rocket::request::Request;
+
+use super::Error;
+use bbs::{dat, topic, Bbs, BoardRef};
+use middleware::{self, token::Token};
+use post::Post;
+use setting;
+use validator::{AlphaNum, Digits};
+
+/// Where a post has been written.
+pub struct Submitted {
+    pub key: u64,
+    /// The 1-based number of the post in the topic.
+    pub number: usize,
+}
+
+// "ＥＲＲＯＲ：サブジェクトが存在しません！"
+const MISSING_SUBJECT: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x83\x54\x83\x75\x83\x57\x83\x46\x83\x4E\x83\x67\x82\xAA\x91\xB6\x8D\xDD\x82\xB5\x82\xDC\x82\xB9\x82\xF1\x81\x49";
+
+/// Posts `post` to the topic `key`, or creates a topic with the title of
+/// `post` if `key` is `None`.
+///
+/// The topic created for a post that is rejected or fails to be written is
+/// removed again.
+pub fn submit<'b, 'r: 'b>(
+    bbs: &Bbs,
+    brd: &'b BoardRef<'b>,
+    board: AlphaNum<'b>,
+    key: Option<Digits<'b>>,
+    post: &mut Post<'r>,
+    token: Option<&'b Token>,
+    req: &'b Request<'r>,
+) -> Result<Submitted, Error<'r>>
+{
+    if brd.settings().get::<setting::common::Heisa>().cloned().unwrap_or(false) {
+        return Err(Error::Closed);
+    }
+    let data = bbs.prepare_middlewares(post, brd.settings())?;
+
+    let key_str;
+    let (key, dat) = if let Some(key) = key {
+        let t = brd.topic_mut(key.number).ok_or(Error::TopicNotFound)?;
+        if t.is_stopped() {
+            return Err(Error::Stopped);
+        }
+        (key, t.into_dat())
+    } else if let Some(title) = post.title() {
+        let mut t = brd.create_topic(title.to_vec()).ok_or(Error::BoardNotFound)?;
+        let noname = topic::noname_command(post.body()).map(Into::into);
+        t.set_noname(noname);
+        let key = unsafe {
+            key_str = t.id().to_string();
+            Digits::new_unchecked(t.id(), &key_str)
+        };
+        (key, t.into_dat())
+    } else {
+        return Err(Error::Invalid(MISSING_SUBJECT.into()));
+    };
+
+    let mut dat = dat.map_err(|e| {
+        error!("failed to open a dat, {}/{}: {:?}", &*board, &*key, e);
+        e
+    })?;
+
+    let applied = {
+        let req = middleware::Request::new(board, key, &dat, token, req);
+        bbs.apply_middlewares(post, data, &req, brd.settings())
+    };
+    let data = match applied {
+        Ok(data) => data,
+        Err(e) => {
+            if let Err(e) = dat.abandon() {
+                warn!("failed to remove an abandoned thread, {}/{}: {:?}", &*board, &*key, e);
+            }
+            return Err(e.into());
+        },
+    };
+
+    // Only the first line of a dat carries the title.
+    let title = if 0 == dat.post_count() { post.title().unwrap_or(b"") } else { b"" };
+    let line = dat::make_line(post.name(), post.mail(), post.datetime(), post.body(), title);
+    let written = dat.append(&line);
+    if let Err(e) = written {
+        error!("failed to write to a dat, {}/{}: {:?}", &*board, &*key, e);
+        if let Err(e) = dat.abandon() {
+            warn!("failed to remove an abandoned thread, {}/{}: {:?}", &*board, &*key, e);
+        }
+        return Err(e.into());
+    }
+    dat.increment_post_count();
+    let number = dat.post_count();
+    bbs.notify_written(post, &data);
+
+    // The post has been written at this point, so the rest only logs
+    // failures rather than telling the poster to post again.
+    if 1 == number {
+        if let Err(e) = dat.archive_overflow() {
+            warn!("failed to archive old threads of {}: {:?}", &*board, e);
+        }
+    }
+    if let Err(e) = dat.stop_if_full() {
+        warn!("failed to stop a full thread, {}/{}: {:?}", &*board, &*key, e);
+    }
+    let sage = post.is_sage();
+    let sage_count = brd.settings().get::<setting::common::SageCount>().cloned();
+    if ! sage && sage_count.map_or(true, |n| dat.post_count() <= n as usize) {
+        dat.bump();
+    }
+    if let Err(e) = dat.persist_subject_txt() {
+        warn!("failed to write subject.txt of {}: {:?}", &*board, e);
+    }
+
+    Ok(Submitted { key: key.number, number })
+}
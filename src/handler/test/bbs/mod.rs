@@ -0,0 +1,159 @@
+use std::borrow::Cow;
+
+use encoding_rs::Encoding;
+use rocket::outcome::Outcome::*;
+use rocket::request::{Form, FromRequest, Outcome, Request};
+
+use super::super::Error;
+use super::super::submit::submit;
+use bbs::Bbs;
+use encoding;
+use post::Post;
+use setting;
+use validator::{AlphaNum, Digits, Escaped, EscapedLines};
+
+#[allow(non_snake_case)]
+#[derive(FromForm)]
+pub struct BbsForm<'r> {
+    bbs: AlphaNum<'r>,
+    key: Option<Digits<'r>>,
+    // time: u64,
+    // submit: &'r str,
+    subject: Option<Escaped<'r>>,
+    FROM: Escaped<'r>,
+    mail: Escaped<'r>,
+    MESSAGE: EscapedLines<'r>,
+}
+
+// "ＥＲＲＯＲ：不正な文字が含まれています！"
+const MALFORMED: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x95\x73\x90\xB3\x82\xC8\x95\xB6\x8E\x9A\x82\xAA\x8A\xDC\x82\xDC\x82\xEA\x82\xC4\x82\xA2\x82\xDC\x82\xB7\x81\x49";
+// "ＥＲＲＯＲ：未対応の文字コードです！"
+const UNSUPPORTED_CHARSET: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x96\xA2\x91\xCE\x89\x9E\x82\xCC\x95\xB6\x8E\x9A\x83\x52\x81\x5B\x83\x68\x82\xC5\x82\xB7\x81\x49";
+
+mod page;
+
+pub use self::page::Page;
+
+pub struct RequestFromRequest<'a, 'r: 'a>(&'a Request<'r>);
+
+#[post("/bbs.cgi", data="<form>")]
+pub fn post<'a, 'r>(form: Form<'r, BbsForm<'r>>, bbs: &'r Bbs, req: RequestFromRequest<'a, 'r>)
+    -> Result<Page<'r>, Page<'r>>
+{
+    let form = form.get();
+
+    let brd = bbs.board(&form.bbs).ok_or(Error::BoardNotFound)?;
+
+    let charset = match req.0.content_type()
+        .and_then(|ct| ct.params().find(|&(k, _)| k.eq_ignore_ascii_case("charset")))
+    {
+        Some((_, label)) => Some(
+            Encoding::for_label(label.as_bytes())
+                .ok_or(Error::Invalid(UNSUPPORTED_CHARSET.into()))?
+        ),
+        None => None,
+    };
+    let pass = brd.settings().get::<setting::common::Unicode>().cloned().unwrap_or(true);
+    let name = transcode(&form.FROM, charset, pass)?;
+    let mail = transcode(&form.mail, charset, pass)?;
+    let body = transcode(&form.MESSAGE, charset, pass)?;
+    let title = match form.subject {
+        Some(ref s) => Some(transcode(s, charset, pass)?),
+        None => None,
+    };
+
+    let mut post = Post::new(name, mail, body, title);
+    let submitted = submit(bbs, &brd, form.bbs, form.key, &mut post, None, req.0);
+    let submitted = submitted.map_err(|e| match e {
+        Error::Confirm => Page::Confirm(confirm_fields(form, &post)),
+        e => Page::from(e),
+    })?;
+
+    let url = format!("read.cgi/{}/{}/", &*form.bbs, submitted.key); // TODO: Post #
+    Ok(Page::Success(url))
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RequestFromRequest<'a, 'r> {
+    type Error = !;
+
+    fn from_request(req: &'a Request<'r>) -> Outcome<Self, !> {
+        Success(RequestFromRequest(req))
+    }
+}
+
+/// Returns the form fields to be echoed in the confirmation page.
+fn confirm_fields(form: &BbsForm, post: &Post) -> Vec<(&'static str, Cow<'static, [u8]>)> {
+    let mut fields = Vec::with_capacity(6);
+    fields.push(("bbs", form.bbs.as_bytes().to_vec().into()));
+    if let Some(key) = form.key {
+        fields.push(("key", key.as_bytes().to_vec().into()));
+    }
+    if let Some(title) = post.title() {
+        fields.push(("subject", hidden_value(title).into()));
+    }
+    fields.push(("FROM", hidden_value(post.name()).into()));
+    fields.push(("mail", hidden_value(post.mail()).into()));
+    fields.push(("MESSAGE", hidden_value(post.body()).into()));
+    fields
+}
+
+/// Escapes a field of `Post` as the value of a hidden field so that the
+/// browser submits what gives the same field again.
+///
+/// `&quot;`, `&lt;` and `&gt;` are left as they are since the browser
+/// turns them back into the characters that bbs.cgi escapes again. Any
+/// other `&` is escaped, and `<br>` becomes a newline along with the
+/// spaces around it.
+fn hidden_value(value: &[u8]) -> Vec<u8> {
+    const KEPT: [&[u8]; 3] = [b"&quot;", b"&lt;", b"&gt;"];
+
+    // Neither `&` nor `<` can be the second byte of a Shift_JIS character.
+    let mut ret = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        let rest = &value[i..];
+        if rest.starts_with(b" <br> ") {
+            ret.extend_from_slice(b"&#10;");
+            i += 6;
+            continue;
+        }
+        if rest.starts_with(b"<br>") {
+            ret.extend_from_slice(b"&#10;");
+            i += 4;
+            continue;
+        }
+        if b'&' == rest[0] && ! KEPT.iter().any(|k| rest.starts_with(k)) {
+            ret.extend_from_slice(b"&amp;");
+        } else {
+            ret.push(rest[0]);
+        }
+        i += 1;
+    }
+    ret
+}
+
+fn transcode<'a>(field: &'a [u8], charset: Option<&'static Encoding>, pass: bool)
+    -> Result<Cow<'a, [u8]>, Error<'static>>
+{
+    encoding::to_shift_jis(field.into(), charset, pass).ok_or(Error::Invalid(MALFORMED.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_values() {
+        // `"&amp;" <b>` typed by the poster.
+        assert_eq!(
+            b"&quot;&amp;amp;&quot; &lt;b&gt;".to_vec(),
+            hidden_value(b"&quot;&amp;&quot; &lt;b&gt;"),
+        );
+        assert_eq!(b"a&#10;b&amp;c".to_vec(), hidden_value(b"a<br>b&c"));
+        assert_eq!(b"a&#10;b".to_vec(), hidden_value(b"a <br> b"));
+        // "ア" and "ぁ" have no special bytes.
+        assert_eq!(b"\x83\x41\x82\x9F".to_vec(), hidden_value(b"\x83\x41\x82\x9F"));
+    }
+}
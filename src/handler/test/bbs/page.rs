@@ -0,0 +1,111 @@
+//! The pages returned by bbs.cgi.
+//!
+//! 2channel browsers tell the outcome of a post from the `<title>` and the
+//! `2ch_X` comment of the page rather than from the status code. Error pages
+//! carry the status of the error as well for the other clients.
+
+use std::borrow::Cow;
+use std::io::{Cursor, Write};
+
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{Responder, Response};
+
+use super::super::super::{shift_jis_html, Error};
+
+#[derive(Debug)]
+pub enum Page<'r> {
+    /// The post has been written. Holds the URL of the topic, relative to
+    /// `/test/`.
+    Success(String),
+    /// The poster has to confirm the post and resubmit the form. Holds the
+    /// names and the values of the form fields to echo, which are escaped
+    /// for attributes.
+    Confirm(Vec<(&'static str, Cow<'r, [u8]>)>),
+    /// The post has been rejected with an HTML-escaped Shift_JIS message.
+    Error(Status, Cow<'r, [u8]>),
+}
+
+const HEAD: &[u8] = b"<html lang=\"ja\"><head>\
+    <meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">";
+
+impl<'r> Page<'r> {
+    fn render(&self) -> Vec<u8> {
+        let mut html = Vec::with_capacity(512);
+        html.extend_from_slice(HEAD);
+
+        match *self {
+            Page::Success(ref url) => {
+                // "書きこみました。"
+                html.extend_from_slice(b"<title>\
+                    \x8F\x91\x82\xAB\x82\xB1\x82\xDD\x82\xDC\x82\xB5\x82\xBD\x81\x42</title>");
+                write!(html, "<meta http-equiv=\"refresh\" content=\"1;URL=../test/{}\">", url)
+                    .unwrap();
+                html.extend_from_slice(b"</head>\n<!-- 2ch_X:true -->\n<body>");
+                // "書きこみが終わりました。"
+                html.extend_from_slice(b"\x8F\x91\x82\xAB\x82\xB1\x82\xDD\x82\xAA\
+                    \x8F\x49\x82\xED\x82\xE8\x82\xDC\x82\xB5\x82\xBD\x81\x42<br><br>\n");
+                // "画面を切り替えるまでしばらくお待ち下さい。"
+                html.extend_from_slice(b"\x89\xE6\x96\xCA\x82\xF0\x90\xD8\x82\xE8\x91\xD6\
+                    \x82\xA6\x82\xE9\x82\xDC\x82\xC5\x82\xB5\x82\xCE\x82\xE7\x82\xAD\x82\xA8\
+                    \x91\xD2\x82\xBF\x89\xBA\x82\xB3\x82\xA2\x81\x42<br><br>\n");
+            },
+            Page::Confirm(ref fields) => {
+                // "■ 書き込み確認 ■"
+                html.extend_from_slice(b"<title>\
+                    \x81\xA1 \x8F\x91\x82\xAB\x8D\x9E\x82\xDD\x8A\x6D\x94\x46 \x81\xA1</title>");
+                html.extend_from_slice(b"</head>\n<!-- 2ch_X:cookie -->\n<body>");
+                // "書き込み確認します。"
+                html.extend_from_slice(b"<font size=\"+1\" color=\"#FF0000\">\
+                    \x8F\x91\x82\xAB\x8D\x9E\x82\xDD\x8A\x6D\x94\x46\x82\xB5\x82\xDC\x82\xB7\x81\x42\
+                    </font><br><br>\n");
+                // "投稿者は、投稿に関して発生する責任が全て投稿者に帰すことを承諾します。"
+                html.extend_from_slice(b"\x93\x8A\x8D\x65\x8E\xD2\x82\xCD\x81\x41\x93\x8A\x8D\x65\
+                    \x82\xC9\x8A\xD6\x82\xB5\x82\xC4\x94\xAD\x90\xB6\x82\xB7\x82\xE9\x90\xD3\x94\x43\
+                    \x82\xAA\x91\x53\x82\xC4\x93\x8A\x8D\x65\x8E\xD2\x82\xC9\x8B\x41\x82\xB7\x82\xB1\
+                    \x82\xC6\x82\xF0\x8F\xB3\x91\xF8\x82\xB5\x82\xDC\x82\xB7\x81\x42<br><br>\n");
+                html.extend_from_slice(b"<form method=\"POST\" action=\"../test/bbs.cgi\">\n");
+                for &(name, ref value) in fields {
+                    write!(html, "<input type=\"hidden\" name=\"{}\" value=\"", name).unwrap();
+                    html.extend_from_slice(value);
+                    html.extend_from_slice(b"\">\n");
+                }
+                // "上記全てを承諾して書き込む"
+                html.extend_from_slice(b"<input type=\"submit\" value=\"\
+                    \x8F\xE3\x8B\x4C\x91\x53\x82\xC4\x82\xF0\x8F\xB3\x91\xF8\x82\xB5\x82\xC4\
+                    \x8F\x91\x82\xAB\x8D\x9E\x82\xDE\">\n</form>\n");
+            },
+            Page::Error(_, ref message) => {
+                // "ＥＲＲＯＲ！"
+                html.extend_from_slice(b"<title>\
+                    \x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x49</title>");
+                html.extend_from_slice(b"</head>\n<!-- 2ch_X:error -->\n<body><b>");
+                html.extend_from_slice(message);
+                html.extend_from_slice(b"</b><br><br>\n");
+            },
+        }
+
+        html.extend_from_slice(b"</body></html>\n");
+        html
+    }
+}
+
+impl<'r> From<Error<'r>> for Page<'r> {
+    fn from(e: Error<'r>) -> Self {
+        Page::Error(e.status(), e.into_message())
+    }
+}
+
+impl<'r> Responder<'r> for Page<'r> {
+    fn respond_to(self, _: &Request) -> Result<Response<'r>, Status> {
+        let status = match self {
+            Page::Error(status, _) => status,
+            _ => Status::Ok,
+        };
+        Response::build()
+            .status(status)
+            .header(shift_jis_html())
+            .sized_body(Cursor::new(self.render()))
+            .ok()
+    }
+}
@@ -0,0 +1,195 @@
+//! read.cgi, which renders a topic in HTML on the server.
+
+mod ranges;
+
+pub use self::ranges::Ranges;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use memchr::memchr;
+use rocket::http::{RawStr, Status};
+use rocket::response::content::Content;
+use rocket::response::status::Custom;
+
+use super::super::{BoardId, BOARD_NOT_FOUND, TOPIC_NOT_FOUND, shift_jis_html};
+use bbs::{Bbs, BoardRef};
+use bbs::dat::{self, Record};
+
+// "："
+const COLON: &[u8] = b"\x81\x46";
+// "ここ壊れてます"
+const BROKEN: &[u8] = b"\x82\xB1\x82\xB1\x89\xF3\x82\xEA\x82\xC4\x82\xDC\x82\xB7";
+
+#[get("/read.cgi/<board>/<key>")]
+pub fn get(board: BoardId, key: u64, bbs: &Bbs)
+    -> Result<Content<Vec<u8>>, Custom<&'static str>>
+{
+    serve(board, key, &Ranges::default(), bbs)
+}
+
+/// A topic with a range, e.g. `/test/read.cgi/<board>/<key>/l50`.
+#[get("/read.cgi/<board>/<key>/<range>")]
+pub fn get_range(board: BoardId, key: u64, range: &RawStr, bbs: &Bbs)
+    -> Result<Content<Vec<u8>>, Custom<&'static str>>
+{
+    serve(board, key, &Ranges::parse(&range.percent_decode_lossy()), bbs)
+}
+
+fn serve(board: BoardId, key: u64, ranges: &Ranges, bbs: &Bbs)
+    -> Result<Content<Vec<u8>>, Custom<&'static str>>
+{
+    let brd = bbs.board(&*board).ok_or(BOARD_NOT_FOUND)?;
+    // Archived topics are rendered as well.
+    let read = match brd.topic(key).map(|t| t.dat()) {
+        Some(dat) => dat.map(|dat| render(&brd, key, dat.body(), ranges)),
+        None => read_kako(&brd, key).map(|dat| render(&brd, key, &dat, ranges)),
+    };
+    match read {
+        Ok(html) => Ok(Content(shift_jis_html(), html)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(TOPIC_NOT_FOUND),
+        Err(_) => Err(Custom(Status::InternalServerError, "Failed to read the dat")),
+    }
+}
+
+fn read_kako(brd: &BoardRef, key: u64) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    File::open(brd.kako_path(key))?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn render(board: &BoardRef, key: u64, dat: &[u8], ranges: &Ranges) -> Vec<u8> {
+    // Reading from a slice never fails.
+    let posts: Vec<Option<Record>> = dat::Reader::new(dat)
+        .map(|r| r.unwrap().ok())
+        .collect();
+    let title = posts.first()
+        .and_then(Option::as_ref)
+        .map_or(&b""[..], |r| &*r.title);
+
+    let mut html = Vec::with_capacity(dat.len() + 2048);
+    html.extend_from_slice(b"<html lang=\"ja\"><head>\
+        <meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">\
+        <link rel=\"stylesheet\" href=\"/test/read.css\"><title>");
+    html.extend_from_slice(title);
+    html.extend_from_slice(b"</title></head>\n<body>\n");
+    write_nav(&mut html, board, key);
+    html.extend_from_slice(b"<h1 class=\"title\">");
+    html.extend_from_slice(title);
+    html.extend_from_slice(b"</h1>\n<dl class=\"thread\">\n");
+
+    for (i, post) in posts.iter().enumerate() {
+        // The first post is always shown.
+        if 0 < i && ! ranges.contains(i, posts.len()) {
+            continue;
+        }
+        match *post {
+            Some(ref post) => write_post(&mut html, board, key, i + 1, post),
+            None => write_broken(&mut html, i + 1),
+        }
+    }
+
+    html.extend_from_slice(b"</dl>\n<hr>\n");
+    write_nav(&mut html, board, key);
+    write!(html, "<form method=\"POST\" accept-charset=\"Shift_JIS\" action=\"/test/bbs.cgi\">\
+        <input type=\"hidden\" name=\"bbs\" value=\"{}\">\
+        <input type=\"hidden\" name=\"key\" value=\"{}\">\n", board.id(), key).unwrap();
+    // "名前"
+    html.extend_from_slice(b"\x96\xBC\x91\x4F<input name=\"FROM\"> ");
+    html.extend_from_slice(b"E-mail<input name=\"mail\"><br>\n\
+        <textarea name=\"MESSAGE\" rows=\"5\" cols=\"70\"></textarea><br>\n");
+    // "書き込む"
+    html.extend_from_slice(b"<input type=\"submit\" value=\"\x8F\x91\x82\xAB\x8D\x9E\x82\xDE\">\
+        \n</form>\n</body></html>\n");
+
+    html
+}
+
+fn write_nav(html: &mut Vec<u8>, board: &BoardRef, key: u64) {
+    let base = format!("/test/read.cgi/{}/{}/", board.id(), key);
+    // "■掲示板に戻る■"
+    write!(html, "<div class=\"nav\"><a href=\"/{}/\">", board.id()).unwrap();
+    html.extend_from_slice(b"\x81\xA1\x8C\x66\x8E\xA6\x94\xC2\x82\xC9\x96\xDF\x82\xE9\x81\xA1</a> ");
+    // "全部"
+    write!(html, "<a href=\"{}\">", base).unwrap();
+    html.extend_from_slice(b"\x91\x53\x95\x94</a> ");
+    write!(html, "<a href=\"{}1-100\">1-100</a> ", base).unwrap();
+    // "最新50"
+    write!(html, "<a href=\"{}l50\">", base).unwrap();
+    html.extend_from_slice(b"\x8D\xC5\x90\x5650</a></div>\n");
+}
+
+fn write_post(html: &mut Vec<u8>, board: &BoardRef, key: u64, n: usize, post: &Record) {
+    let (name, mail) = (&post.name[..], &post.mail[..]);
+
+    write!(html, "<dt id=\"{0}\">{0} ", n).unwrap();
+    html.extend_from_slice(COLON);
+    if mail.is_empty() {
+        html.extend_from_slice(b"<font color=\"green\"><b>");
+        html.extend_from_slice(name);
+        html.extend_from_slice(b"</b></font>");
+    } else {
+        html.extend_from_slice(b"<a href=\"mailto:");
+        html.extend_from_slice(mail);
+        html.extend_from_slice(b"\"><b>");
+        html.extend_from_slice(name);
+        html.extend_from_slice(b"</b></a>");
+    }
+    html.extend_from_slice(COLON);
+    html.extend_from_slice(&post.datetime_with_id());
+    html.extend_from_slice(b"<dd>");
+    write_body(html, board, key, &post.body);
+    html.extend_from_slice(b"<br><br>\n");
+}
+
+/// Writes a post that could not be parsed, as 2channel does.
+fn write_broken(html: &mut Vec<u8>, n: usize) {
+    write!(html, "<dt id=\"{0}\">{0} ", n).unwrap();
+    for _ in 0..2 {
+        html.extend_from_slice(COLON);
+        html.extend_from_slice(BROKEN);
+    }
+    html.extend_from_slice(b"<dd>");
+    html.extend_from_slice(BROKEN);
+    html.extend_from_slice(b"<br><br>\n");
+}
+
+/// Writes `body` with `>>n` anchors (escaped as `&gt;&gt;n`) turned into
+/// links to the posts.
+fn write_body(html: &mut Vec<u8>, board: &BoardRef, key: u64, body: &[u8]) {
+    const ANCHOR: &[u8] = b"&gt;&gt;";
+
+    let mut rest = body;
+    while let Some(i) = memchr(b'&', rest) {
+        if ! rest[i..].starts_with(ANCHOR) {
+            html.extend_from_slice(&rest[..(i+1)]);
+            rest = &rest[(i+1)..];
+            continue;
+        }
+
+        let after = &rest[(i+ANCHOR.len())..];
+        let mut len = 0;
+        if after.first().map_or(false, u8::is_ascii_digit) {
+            len = after.iter()
+                .take_while(|&&c| c.is_ascii_digit() || b'-' == c || b',' == c)
+                .count();
+            while b',' == after[len-1] {
+                len -= 1;
+            }
+        }
+        html.extend_from_slice(&rest[..i]);
+        if 0 == len {
+            html.extend_from_slice(ANCHOR);
+        } else {
+            let range = &after[..len];
+            write!(html, "<a href=\"/test/read.cgi/{}/{}/", board.id(), key).unwrap();
+            html.extend_from_slice(range);
+            html.extend_from_slice(b"\">");
+            html.extend_from_slice(ANCHOR);
+            html.extend_from_slice(range);
+            html.extend_from_slice(b"</a>");
+        }
+        rest = &after[len..];
+    }
+    html.extend_from_slice(rest);
+}
@@ -0,0 +1,125 @@
+//! The range syntax of read.cgi, e.g. `-10,15,20-30,500-,l50`.
+
+use std::cmp;
+use std::usize;
+
+/// A set of post numbers, which are 0-based here.
+#[derive(Debug, Default, PartialEq)]
+pub struct Ranges {
+    /// Sorted, disjoint and non-adjacent inclusive intervals.
+    intervals: Vec<(usize, usize)>,
+    /// The number of the last posts included, given by `lN`.
+    from_last: usize,
+}
+
+impl Ranges {
+    /// Parses a comma-separated list of ranges, ignoring invalid ones.
+    /// Anything after an `lN` is ignored.
+    pub fn parse(s: &str) -> Self {
+        let mut intervals = Vec::new();
+        let mut from_last = 0;
+
+        for r in s.split(',') {
+            let r = r.trim();
+            if r.starts_with('l') {
+                if let Some(n) = parse_digits(&r[1..]) {
+                    from_last = cmp::max(from_last, n);
+                    break;
+                }
+                continue;
+            }
+
+            let (begin, end) = match r.find('-') {
+                Some(i) => {
+                    let (b, e) = (&r[..i], &r[(i+1)..]);
+                    let begin = if b.is_empty() { Some(0) } else { parse_digits(b) };
+                    let end = if e.is_empty() {
+                        Some(usize::MAX)
+                    } else {
+                        parse_digits(e).and_then(|n| n.checked_sub(1))
+                    };
+                    match (begin, end) {
+                        (Some(b), Some(e)) => (b.saturating_sub(1), e),
+                        _ => continue,
+                    }
+                },
+                None => match parse_digits(r).and_then(|n| n.checked_sub(1)) {
+                    Some(n) => (n, n),
+                    None => continue,
+                },
+            };
+            if begin <= end {
+                intervals.push((begin, end));
+            }
+        }
+
+        intervals.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(intervals.len());
+        for (begin, end) in intervals {
+            if let Some(last) = merged.last_mut() {
+                if begin <= last.1.saturating_add(1) {
+                    last.1 = cmp::max(last.1, end);
+                    continue;
+                }
+            }
+            merged.push((begin, end));
+        }
+
+        Ranges { intervals: merged, from_last }
+    }
+
+    /// Returns whether the ranges select every post, which is the case
+    /// without intervals. `lN` only adds the last posts to the intervals, so
+    /// a bare `lN` selects every post as well.
+    pub fn is_all(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Returns whether the `i`-th post of a topic with `len` posts is
+    /// selected.
+    pub fn contains(&self, i: usize, len: usize) -> bool {
+        self.is_all()
+            || (0 < self.from_last && i >= len.saturating_sub(self.from_last))
+            || self.intervals.iter().any(|&(begin, end)| begin <= i && i <= end)
+    }
+}
+
+fn parse_digits(s: &str) -> Option<usize> {
+    if ! s.is_empty() && s.bytes().all(|c| c.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let r = Ranges::parse("-10,15,20-30,500-,l50");
+        assert_eq!(vec![(0, 9), (14, 14), (19, 29), (499, usize::MAX)], r.intervals);
+        assert_eq!(50, r.from_last);
+
+        // Overlapping and adjoining ranges are merged.
+        assert_eq!(vec![(0, 19)], Ranges::parse("5-10,1-5,11-20").intervals);
+        // Invalid ranges are ignored.
+        assert_eq!(vec![(2, 2)], Ranges::parse("0,x,10-5,3").intervals);
+        // Anything after `lN` is ignored.
+        assert_eq!(Ranges { intervals: vec![], from_last: 10 }, Ranges::parse("l10,5"));
+        assert!(Ranges::parse("").is_all());
+    }
+
+    #[test]
+    fn contains() {
+        let r = Ranges::parse("2-3,l2");
+        let selected: Vec<usize> = (0..10).filter(|&i| r.contains(i, 10)).collect();
+        assert_eq!(vec![1, 2, 8, 9], selected);
+
+        // A bare `lN` selects every post.
+        let r = Ranges::parse("l2");
+        assert!(r.is_all());
+        assert!((0..10).all(|i| r.contains(i, 10)));
+    }
+}
@@ -15,13 +15,23 @@ extern crate atoi;
 extern crate cfg_if;
 extern crate checked;
 extern crate chrono;
+extern crate encoding_rs;
 extern crate hyper;
 extern crate lazy_init;
+#[macro_use]
+extern crate log;
 extern crate memchr;
 extern crate owning_ref;
 extern crate parking_lot;
 extern crate percent_encoding;
+extern crate pwhash;
+extern crate rand;
 extern crate rocket;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate sha1;
 extern crate time;
 extern crate typemap;
 
@@ -31,6 +41,7 @@ pub mod middleware;
 pub mod post;
 pub mod setting;
 
+mod encoding;
 mod responder;
 mod util;
 mod validator;
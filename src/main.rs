@@ -4,17 +4,81 @@
 extern crate monaxide;
 extern crate rocket;
 
+use std::env;
+use std::io;
+
+use monaxide::bbs::SyncPolicy;
+use monaxide::middleware::cap::Cap;
+use monaxide::middleware::confirm::Confirm;
 use monaxide::middleware::datetime::DateTime;
+use monaxide::middleware::duplicate::Duplicate;
+use monaxide::middleware::id::Id;
+use monaxide::middleware::limit::Limit;
+use monaxide::middleware::noname::Noname;
+use monaxide::middleware::samba::Samba;
+use monaxide::middleware::token::Tokens;
+use monaxide::middleware::trip::Tripcode;
 
 fn main() {
     use monaxide::handler::*;
 
     let mut bbs = monaxide::Bbs::new().unwrap();
+    // `MONAXIDE_SYNC=never` trades the last posts on a power failure for
+    // faster appends.
+    match env::var("MONAXIDE_SYNC") {
+        Ok(ref v) if "never" == v => { bbs.set_sync_policy(SyncPolicy::Never); },
+        Ok(ref v) if "always" == v => {},
+        Ok(v) => panic!("unknown MONAXIDE_SYNC, {}: expected `always` or `never`", v),
+        Err(_) => {},
+    }
+    // Rejects posts over the limits before asking for a confirmation.
+    bbs.attach_before(Limit);
+    bbs.attach_before(Confirm::load("CONFIRM_SECRET").unwrap());
+    bbs.attach_before(Samba::new());
     bbs.attach(DateTime::with_jst());
+    bbs.attach(Duplicate::new());
+    bbs.attach(Id::load("ID_SECRET").unwrap());
+    bbs.attach(Noname);
+    bbs.attach_after(Tripcode);
+    match Cap::load("CAPS") {
+        Ok(cap) => { bbs.attach(cap); },
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => {},
+        Err(e) => panic!("failed to load CAPS: {:?}", e),
+    }
+
+    let mut rocket = rocket::ignite().manage(bbs);
+    match Tokens::load("API_TOKENS") {
+        Ok(tokens) => { rocket = rocket.manage(tokens); },
+        Err(ref e) if io::ErrorKind::NotFound == e.kind() => {},
+        Err(e) => panic!("failed to load API_TOKENS: {:?}", e),
+    }
 
-    rocket::ignite()
-        .manage(bbs)
-        .mount("/", routes![board::get, board::dat::get, board::setting_txt::get])
-        .mount("/test", routes![test::bbs::post, test::read::get])
+    rocket
+        .mount("/", routes![
+            board::get,
+            board::dat::get,
+            board::kako::get,
+            board::kako::get_short,
+            board::setting_txt::get,
+            board::subject_txt::get,
+        ])
+        .mount("/api", routes![
+            api::subject,
+            api::topic,
+            api::topic_since,
+            api::post::reply,
+            api::post::create,
+        ])
+        .mount("/admin", routes![
+            api::admin::create_board,
+            api::admin::retire_board,
+            api::admin::remove_board,
+            api::admin::stop_topic,
+            api::admin::delete_post,
+            api::admin::delete_topic,
+            api::admin::edit_title,
+        ])
+        .mount("/test", routes![test::bbs::post, test::read::get, test::read::get_range])
+        .catch(errors![error::not_found, error::internal_error])
         .launch();
 }
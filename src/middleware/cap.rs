@@ -1,7 +1,189 @@
-use typemap::Key;
+//! Cap (capcode) authentication through the mail field.
+//!
+//! A poster logs in with `#capid#cappass` appended to the mail field, which
+//! is verified before the topic is locked since hashing is slow. Caps are
+//! read from a store file with lines of the form:
+//!
+//! ```text
+//! capid<>hash<>label
+//! ```
+//!
+//! where `hash` is a bcrypt (`$2b$...`) or SHA-512 crypt (`$6$...`) hash of
+//! the password, as made by `Cap::hash_password` or `mkpasswd`. Lines with
+//! other hashes are ignored.
+//!
+//! The label is appended to the name in place of the default " ★" if
+//! present. Whether or not the login succeeds, everything after the `#` is
+//! removed from the mail field by `Middlewares::apply`, so the password is
+//! never written to the dat even without this middleware.
+//!
+//! A post authenticated with an API token is made under the cap configured
+//! for the token, without a password.
 
-pub struct Cap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use pwhash::{bcrypt, sha512_crypt};
+use typemap::{Key, ShareMap};
+
+use super::{AfterMiddleware, BeforeMiddleware, Request, Result};
+use super::token::Token;
+use post::Post;
+use setting::Settings;
+
+/// The middleware, and the key of the label of the authenticated cap.
+///
+/// This must be attached after the other middlewares that modify the name
+/// field since those may replace the "★" appended by this.
+pub struct Cap {
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    id: Box<[u8]>,
+    hash: String,
+    label: Option<Box<[u8]>>,
+}
 
 impl Key for Cap {
     type Value = Box<[u8]>;
 }
+
+// " ★"
+const DEFAULT_LABEL: &[u8] = b" \x81\x9A";
+
+impl Cap {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Cap::_load(path.as_ref())
+    }
+
+    fn _load(path: &Path) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(path)?).split(b'\n') {
+            let line = line?;
+            let mut fields = split_fields(&line).into_iter();
+            let (id, hash) = match (fields.next(), fields.next()) {
+                (Some(id), Some(hash)) if ! id.is_empty() => (id, hash),
+                _ => continue,
+            };
+            let hash = match ::std::str::from_utf8(hash) {
+                Ok(hash) if hash.starts_with("$2") || hash.starts_with("$6$") => hash,
+                _ => {
+                    warn!("ignoring a cap with an unsupported hash, {}",
+                        String::from_utf8_lossy(id));
+                    continue;
+                },
+            };
+            let label = fields.next().filter(|l| ! l.is_empty());
+            entries.push(Entry {
+                id: id.into(),
+                hash: hash.to_owned(),
+                label: label.map(Into::into),
+            });
+        }
+        Ok(Cap { entries })
+    }
+
+    /// Hashes a password with bcrypt in the format of the store file.
+    pub fn hash_password(pass: &[u8]) -> io::Result<String> {
+        bcrypt::hash(pass).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+
+    /// Verifies `capid#cappass` against the cap `capid` alone, so that a
+    /// post costs a single hash however many caps there are.
+    fn authenticate(&self, credential: &[u8]) -> Option<&Entry> {
+        let i = credential.iter().position(|&c| b'#' == c)?;
+        let (id, pass) = (&credential[..i], &credential[(i+1)..]);
+        self.entries.iter().find(|e| *id == *e.id).filter(|e| e.verify(pass))
+    }
+
+    fn insert_label(data: &mut ShareMap, entry: &Entry) {
+        let label = entry.label.as_ref().map_or(DEFAULT_LABEL, |l| &**l);
+        data.insert::<Cap>(label.into());
+    }
+}
+
+impl Entry {
+    /// Checks `pass` against the hash, which is compared in constant time.
+    fn verify(&self, pass: &[u8]) -> bool {
+        if self.hash.starts_with("$6$") {
+            sha512_crypt::verify(pass, &self.hash)
+        } else {
+            bcrypt::verify(pass, &self.hash)
+        }
+    }
+}
+
+impl BeforeMiddleware for Cap {
+    fn prepare(&self, data: &mut ShareMap, post: &Post, _: &Settings) -> Result<'static, ()> {
+        let entry = post.mail().iter().position(|&c| b'#' == c)
+            .and_then(|i| self.authenticate(&post.mail()[(i+1)..]));
+        if let Some(e) = entry {
+            Cap::insert_label(data, e);
+        }
+        Ok(())
+    }
+
+    fn before<'a, 'r, 'b, 'k>(
+        &self, data: &mut ShareMap, _: &Post, req: &Request<'a, 'r, 'b, 'k>, _: &Settings
+    ) -> Result<'r, ()>
+    {
+        // The cap of the token takes precedence over the mail field.
+        let entry = req.token().and_then(Token::cap)
+            .and_then(|id| self.entries.iter().find(|e| *e.id == *id));
+        if let Some(e) = entry {
+            Cap::insert_label(data, e);
+        }
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for Cap {
+    fn after(&self, post: &mut Post, data: &ShareMap, _: &Settings) -> Result<'static, ()> {
+        if let Some(label) = data.get::<Cap>() {
+            post.name_mut().extend_from_slice(label);
+        }
+        Ok(())
+    }
+}
+
+fn split_fields(mut line: &[u8]) -> Vec<&[u8]> {
+    let mut ret = Vec::with_capacity(4);
+    while let Some(i) = line.windows(2).position(|w| w == b"<>") {
+        ret.push(&line[..i]);
+        line = &line[(i+2)..];
+    }
+    ret.push(line);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authenticate() {
+        let cap = Cap {
+            entries: vec![
+                Entry {
+                    id: b"bcrypt"[..].into(),
+                    hash: Cap::hash_password(b"pass").unwrap(),
+                    label: None,
+                },
+                Entry {
+                    id: b"sha512"[..].into(),
+                    hash: sha512_crypt::hash(b"word").unwrap(),
+                    label: None,
+                },
+            ],
+        };
+        assert_eq!(Some(&b"bcrypt"[..]), cap.authenticate(b"bcrypt#pass").map(|e| &*e.id));
+        assert_eq!(Some(&b"sha512"[..]), cap.authenticate(b"sha512#word").map(|e| &*e.id));
+        assert!(cap.authenticate(b"bcrypt#word").is_none());
+        assert!(cap.authenticate(b"bcrypt#Pass").is_none());
+        // The cap id is required.
+        assert!(cap.authenticate(b"pass").is_none());
+        assert!(cap.authenticate(b"nobody#pass").is_none());
+    }
+}
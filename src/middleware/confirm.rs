@@ -0,0 +1,170 @@
+//! The confirmation of first-time posters (_書き込み確認_).
+//!
+//! A poster without a valid confirmation cookie is shown the confirmation
+//! page, which sets the cookie, and the post is accepted only when it is
+//! submitted again with the cookie. The cookie is of the form
+//! `nonce.hex(HMAC-SHA1(secret, nonce))` so that it cannot be forged
+//! without the secret.
+//!
+//! Like 2channel, this also remembers the name and mail fields in the `NAME`
+//! and `MAIL` cookies.
+
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+use rand::{self, Rng};
+use rocket::http::Cookie;
+use sha1::Sha1;
+use time::Duration;
+use typemap::ShareMap;
+
+use super::{BeforeMiddleware, Error, Request, Result};
+use post::Post;
+use setting::Settings;
+
+pub struct Confirm {
+    /// The secret padded to the block size of SHA-1 for HMAC.
+    key: [u8; BLOCK_LEN],
+}
+
+const BLOCK_LEN: usize = 64;
+
+const CONFIRM_COOKIE: &str = "CONFIRM";
+const NAME_COOKIE: &str = "NAME";
+const MAIL_COOKIE: &str = "MAIL";
+
+impl Confirm {
+    pub fn new(secret: Vec<u8>) -> Self {
+        let mut key = [0; BLOCK_LEN];
+        if secret.len() > BLOCK_LEN {
+            let mut h = Sha1::new();
+            h.update(&secret);
+            key[..20].copy_from_slice(&h.digest().bytes());
+        } else {
+            key[..secret.len()].copy_from_slice(&secret);
+        }
+        Confirm { key }
+    }
+
+    /// Loads the secret from `path`, creating the file with a random secret
+    /// if it does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        super::load_secret(path.as_ref()).map(Confirm::new)
+    }
+
+    /// Computes HMAC-SHA1 (RFC 2104) of `nonce` in hexadecimal.
+    fn sign(&self, nonce: &str) -> String {
+        let mut pad = [0; BLOCK_LEN];
+        for (p, k) in pad.iter_mut().zip(self.key.iter()) { *p = k ^ 0x36; }
+        let mut inner = Sha1::new();
+        inner.update(&pad);
+        inner.update(nonce.as_bytes());
+
+        for (p, k) in pad.iter_mut().zip(self.key.iter()) { *p = k ^ 0x5C; }
+        let mut outer = Sha1::new();
+        outer.update(&pad);
+        outer.update(&inner.digest().bytes());
+        outer.digest().to_string()
+    }
+
+    fn make_token(&self) -> String {
+        let nonce = format!("{:016x}", rand::thread_rng().gen::<u64>());
+        let sig = self.sign(&nonce);
+        format!("{}.{}", nonce, sig)
+    }
+
+    fn verify(&self, token: &str) -> bool {
+        match token.find('.') {
+            Some(i) => {
+                let sig = self.sign(&token[..i]);
+                constant_time_eq(sig.as_bytes(), token[(i+1)..].as_bytes())
+            },
+            None => false,
+        }
+    }
+}
+
+impl BeforeMiddleware for Confirm {
+    fn before<'a, 'r, 'b, 'k>(
+        &self, _: &mut ShareMap, post: &Post, req: &Request<'a, 'r, 'b, 'k>, _: &Settings
+    ) -> Result<'r, ()>
+    {
+        // Bots authenticated with an API token cannot handle cookies.
+        if req.token().is_some() {
+            return Ok(());
+        }
+
+        let mut cookies = req.cookies();
+
+        // Never let a cap password stay in the cookie.
+        let mail = match post.mail().iter().position(|&c| b'#' == c) {
+            Some(i) => &post.mail()[..i],
+            None => post.mail(),
+        };
+        cookies.add(remember(NAME_COOKIE, post.name()));
+        cookies.add(remember(MAIL_COOKIE, mail));
+
+        let confirmed = cookies.get(CONFIRM_COOKIE).map_or(false, |c| self.verify(c.value()));
+        if confirmed {
+            return Ok(());
+        }
+
+        let cookie = Cookie::build(CONFIRM_COOKIE, self.make_token())
+            .path("/")
+            .max_age(Duration::days(365))
+            .http_only(true)
+            .finish();
+        cookies.add(cookie);
+        Err(Error::Confirm)
+    }
+}
+
+/// Compares `a` and `b` in time that depends only on their lengths, so that
+/// a signature cannot be guessed byte by byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && 0 == a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y))
+}
+
+/// Makes a cookie holding a percent-encoded Shift_JIS field.
+fn remember(name: &'static str, value: &[u8]) -> Cookie<'static> {
+    let mut encoded = String::with_capacity(value.len() * 3);
+    for &c in value {
+        if c.is_ascii_alphanumeric() || b"-_.!~*'()".contains(&c) {
+            encoded.push(c as char);
+        } else {
+            write!(encoded, "%{:02X}", c).unwrap();
+        }
+    }
+    Cookie::build(name, encoded)
+        .path("/")
+        .max_age(Duration::days(365))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac() {
+        // RFC 2202, test cases 1 and 6.
+        let confirm = Confirm::new(vec![0x0B; 20]);
+        assert_eq!("b617318655057264e28bc0b6fb378c8ef146be00", confirm.sign("Hi There"));
+        let confirm = Confirm::new(vec![0xAA; 80]);
+        assert_eq!(
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112",
+            confirm.sign("Test Using Larger Than Block-Size Key - Hash Key First")
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let confirm = Confirm::new(vec![0; 32]);
+        let token = confirm.make_token();
+        assert!(confirm.verify(&token));
+        assert!(! confirm.verify(&token[..(token.len()-1)]));
+        assert!(! Confirm::new(vec![1; 32]).verify(&token));
+        assert!(! confirm.verify("0123456789abcdef"));
+    }
+}
@@ -0,0 +1,256 @@
+//! Detection of duplicate posts (_二重書き込み_).
+//!
+//! A post is rejected if its body is the same as the last post of the topic,
+//! or as a post from the same network within `BBS_DUPLICATE_TIME` seconds.
+//! With `BBS_DUPLICATE_BOARD`, the latter also applies across the topics of
+//! the board.
+//!
+//! Only a bounded number of recent fingerprints are kept in memory, so this
+//! is not meant to catch every duplicate.
+
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use typemap::{Key, ShareMap};
+
+use super::{AfterMiddleware, BeforeMiddleware, Error, Request, Result};
+use post::Post;
+use setting::Settings;
+use setting::common::{DuplicateBoard, DuplicateTime};
+
+pub struct Duplicate {
+    hasher: RandomState,
+    state: Mutex<State>,
+}
+
+/// The post being checked, which is recorded once it has been written.
+pub struct Pending {
+    board: Box<str>,
+    key: u64,
+    record: Record,
+}
+
+impl Key for Duplicate {
+    type Value = Pending;
+}
+
+#[derive(Default)]
+struct State {
+    topics: HashMap<(Box<str>, u64), VecDeque<Record>>,
+    boards: HashMap<Box<str>, VecDeque<Record>>,
+    last_eviction: Option<Instant>,
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    fingerprint: u64,
+    client: Option<[u8; 16]>,
+    at: Instant,
+}
+
+const DEFAULT_DUPLICATE_TIME: u32 = 5 * 60;
+
+const MAX_TOPIC_RECORDS: usize = 16;
+const MAX_BOARD_RECORDS: usize = 64;
+/// The records of a topic or a board are forgotten when nothing has been
+/// posted to it for this long.
+const EXPIRY_SECS: u64 = 24 * 60 * 60;
+const EVICTION_INTERVAL_SECS: u64 = 10 * 60;
+
+// "ＥＲＲＯＲ：二重かきこですか？？"
+const DUPLICATE: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x93\xF1\x8F\x64\x82\xA9\x82\xAB\x82\xB1\x82\xC5\x82\xB7\x82\xA9\x81\x48\x81\x48";
+
+impl Duplicate {
+    pub fn new() -> Self {
+        Duplicate {
+            hasher: RandomState::new(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn fingerprint(&self, body: &[u8]) -> u64 {
+        let mut h = self.hasher.build_hasher();
+        body.hash(&mut h);
+        h.finish()
+    }
+}
+
+impl Default for Duplicate {
+    fn default() -> Self {
+        Duplicate::new()
+    }
+}
+
+impl BeforeMiddleware for Duplicate {
+    fn before<'a, 'r, 'b, 'k>(
+        &self, data: &mut ShareMap, post: &Post, req: &Request<'a, 'r, 'b, 'k>, settings: &Settings
+    ) -> Result<'r, ()>
+    {
+        let window = settings.get::<DuplicateTime>().cloned().unwrap_or(DEFAULT_DUPLICATE_TIME);
+        let window = Duration::from_secs(window as u64);
+        let per_board = settings.get::<DuplicateBoard>().cloned().unwrap_or(false);
+        let board = req.board().to_ascii_lowercase().into_boxed_str();
+        let record = Record {
+            fingerprint: self.fingerprint(post.body()),
+            client: req.remote().map(|addr| super::network(addr.ip())),
+            at: Instant::now(),
+        };
+
+        let pending = Pending { board, key: req.key(), record };
+
+        let mut state = self.state.lock();
+        state.evict(record.at);
+        if state.is_duplicate(&pending, window, per_board) {
+            return Err(Error::Throttled(DUPLICATE.into()));
+        }
+
+        data.insert::<Duplicate>(pending);
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for Duplicate {
+    fn after(&self, _: &mut Post, _: &ShareMap, _: &Settings) -> Result<'static, ()> {
+        Ok(())
+    }
+
+    fn written(&self, _: &Post, data: &ShareMap) {
+        // A post that fails to be written must not block its retry.
+        if let Some(pending) = data.get::<Duplicate>() {
+            self.state.lock().record(pending);
+        }
+    }
+}
+
+impl State {
+    fn is_duplicate(&self, pending: &Pending, window: Duration, per_board: bool) -> bool {
+        let record = &pending.record;
+        let is_dup = |r: &Record| {
+            r.fingerprint == record.fingerprint
+                && r.client.is_some()
+                && r.client == record.client
+                && record.at - r.at < window
+        };
+        if let Some(records) = self.topics.get(&(pending.board.clone(), pending.key)) {
+            let last = records.back().map_or(false, |r| r.fingerprint == record.fingerprint);
+            if last || records.iter().any(&is_dup) {
+                return true;
+            }
+        }
+        per_board && self.boards.get(&pending.board).map_or(false, |records| {
+            records.iter().any(&is_dup)
+        })
+    }
+
+    fn record(&mut self, pending: &Pending) {
+        push(
+            self.topics.entry((pending.board.clone(), pending.key)).or_insert_with(VecDeque::new),
+            pending.record,
+            MAX_TOPIC_RECORDS,
+        );
+        push(
+            self.boards.entry(pending.board.clone()).or_insert_with(VecDeque::new),
+            pending.record,
+            MAX_BOARD_RECORDS,
+        );
+    }
+
+    /// Forgets the topics and boards that have been quiet for `EXPIRY_SECS`,
+    /// at most once in `EVICTION_INTERVAL_SECS`.
+    fn evict(&mut self, now: Instant) {
+        let interval = Duration::from_secs(EVICTION_INTERVAL_SECS);
+        if self.last_eviction.map_or(false, |t| now - t < interval) {
+            return;
+        }
+        self.last_eviction = Some(now);
+
+        let expiry = Duration::from_secs(EXPIRY_SECS);
+        let alive = |records: &mut VecDeque<Record>| {
+            records.back().map_or(false, |r| now - r.at < expiry)
+        };
+        self.topics.retain(|_, records| alive(records));
+        self.boards.retain(|_, records| alive(records));
+    }
+}
+
+fn push(records: &mut VecDeque<Record>, record: Record, max: usize) {
+    if records.len() >= max {
+        records.pop_front();
+    }
+    records.push_back(record);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 60;
+
+    fn pending(key: u64, fingerprint: u64, client: u8, at: Instant) -> Pending {
+        let mut net = [0; 16];
+        net[0] = client;
+        Pending {
+            board: "news".into(),
+            key,
+            record: Record { fingerprint, client: Some(net), at },
+        }
+    }
+
+    #[test]
+    fn same_topic() {
+        let t = Instant::now();
+        let secs = |n| t + Duration::from_secs(n);
+        let window = Duration::from_secs(WINDOW);
+        let mut state = State::default();
+        assert!(! state.is_duplicate(&pending(1, 42, 1, t), window, false));
+        state.record(&pending(1, 42, 1, t));
+
+        // The last post of a topic is never repeated, whoever posts it.
+        assert!(state.is_duplicate(&pending(1, 42, 2, secs(1)), window, false));
+        assert!(! state.is_duplicate(&pending(1, 43, 1, secs(1)), window, false));
+
+        state.record(&pending(1, 43, 2, secs(1)));
+        assert!(state.is_duplicate(&pending(1, 42, 1, secs(2)), window, false));
+        assert!(! state.is_duplicate(&pending(1, 42, 2, secs(2)), window, false));
+        assert!(! state.is_duplicate(&pending(1, 42, 1, secs(WINDOW)), window, false));
+    }
+
+    #[test]
+    fn same_board() {
+        let t = Instant::now();
+        let window = Duration::from_secs(WINDOW);
+        let mut state = State::default();
+        state.record(&pending(1, 42, 1, t));
+        let other = pending(2, 42, 1, t + Duration::from_secs(1));
+        assert!(! state.is_duplicate(&other, window, false));
+        assert!(state.is_duplicate(&other, window, true));
+    }
+
+    #[test]
+    fn unwritten() {
+        let t = Instant::now();
+        let secs = |n| t + Duration::from_secs(n);
+        let window = Duration::from_secs(WINDOW);
+        let dup = Duplicate::new();
+        let post = Post::new(&b""[..], &b""[..], &b""[..], None);
+        let is_duplicate = |p: &Pending| dup.state.lock().is_duplicate(p, window, true);
+        let mut data = ShareMap::custom();
+        data.insert::<Duplicate>(pending(1, 42, 1, t));
+
+        // A post that has not been written is not recorded, even after
+        // another post has been.
+        let mut other = ShareMap::custom();
+        other.insert::<Duplicate>(pending(1, 43, 2, secs(1)));
+        dup.written(&post, &other);
+        assert!(is_duplicate(&pending(1, 43, 1, secs(2))));
+        assert!(! is_duplicate(&pending(1, 42, 1, secs(2))));
+
+        // It is once written.
+        dup.written(&post, &data);
+        assert!(is_duplicate(&pending(1, 42, 1, secs(3))));
+    }
+}
@@ -1,14 +1,25 @@
 use std::io::{self, Write};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 
+use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+use sha1::Sha1;
 use typemap::{Key, ShareMap};
 
 use super::{AfterMiddleware, BeforeMiddleware, Request, Result};
 use super::cap::Cap;
+use super::datetime::Jst;
 use post::Post;
 use setting::{self, Settings};
 
-pub struct Id;
+/// A middleware that appends a poster ID to the datetime field.
+///
+/// The ID is a hash of the remote address, the board id and the date in JST
+/// keyed with a secret, so it is stable within a board for a day and cannot
+/// be reversed into the address.
+pub struct Id {
+    secret: Box<[u8]>,
+}
 
 impl Key for Id {
     type Value = IdHash;
@@ -19,25 +30,64 @@ pub struct IdHash {
     suffix: u8,
 }
 
+impl Id {
+    pub fn new(secret: Vec<u8>) -> Self {
+        Id { secret: secret.into() }
+    }
+
+    /// Loads the secret from `path`, creating the file with a random secret
+    /// if it does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        super::load_secret(path.as_ref()).map(Id::new)
+    }
+
+    fn generate_id(&self, addr: SocketAddr, board: &str, date: NaiveDate) -> IdHash {
+        let mut h = Sha1::new();
+        h.update(&self.secret);
+        match addr.ip() {
+            IpAddr::V4(ip) => h.update(&ip.octets()),
+            // Hosts in the same /64 network are usually the same client.
+            IpAddr::V6(ip) => h.update(&ip.octets()[..8]),
+        }
+        h.update(b"\0");
+        h.update(board.to_ascii_lowercase().as_bytes());
+        h.update(b"\0");
+        h.update(&[
+            (date.year() >> 8) as u8,
+            date.year() as u8,
+            date.month() as u8,
+            date.day() as u8,
+        ]);
+
+        let digest = h.digest().bytes();
+        let mut hash = 0;
+        for &b in &digest[..8] {
+            hash = (hash << 8) | b as u64;
+        }
+
+        IdHash {
+            hash,
+            suffix: b'0',
+        }
+    }
+}
+
 impl BeforeMiddleware for Id {
     fn before<'a, 'r, 'b, 'k>(
         &self, data: &mut ShareMap, _: &Post, req: &Request<'a, 'r, 'b, 'k>, settings: &Settings
     ) -> Result<'r, ()>
     {
-        fn generate_id(_addr: SocketAddr) -> IdHash {
-            IdHash {
-                hash: 0,
-                suffix: b'0',
-            };
-            unimplemented!();
+        if settings.get::<setting::common::NoId>().cloned().unwrap_or(false)
+            || data.contains::<Cap>()
+        {
+            return Ok(());
         }
 
-        if (! data.contains::<Cap>()) && settings.get::<setting::common::ForceId>().cloned().unwrap_or(true) {
-            if let Some(r) = req.remote() {
-                data.insert::<Id>(generate_id(r));
-            } else {
-                return Err((b"Remote address unknown" as &[u8]).into());
-            }
+        if let Some(r) = req.remote() {
+            let today = Jst.from_utc_datetime(&Utc::now().naive_utc()).naive_local().date();
+            data.insert::<Id>(self.generate_id(r, req.board(), today));
+        } else {
+            return Err((b"Remote address unknown" as &[u8]).into());
         }
 
         Ok(())
@@ -46,13 +96,23 @@ impl BeforeMiddleware for Id {
 
 impl AfterMiddleware for Id {
     fn after(&self, post: &mut Post, data: &ShareMap, settings: &Settings) -> Result<'static, ()> {
+        // `Cap` may be attached after `Id`, so check it here as well.
+        let id = match data.get::<Id>() {
+            Some(id) if ! data.contains::<Cap>() => id,
+            _ => return Ok(()),
+        };
+
+        // Without `BBS_FORCE_ID`, posters can hide their IDs with `sage`.
+        let force = settings.get::<setting::common::ForceId>().cloned().unwrap_or(true);
+        let sage = post.is_sage();
+
         let dt = post.datetime_mut();
-        if let Some(_id) = data.get::<Id>() {
+        if force || ! sage {
             // "ID:abcdefgh0"
             super::reserve_and_delimit(dt, 12);
             dt.extend_from_slice(b"ID:");
-            unimplemented!();
-        } else if settings.get::<setting::common::ForceId>().cloned().unwrap_or(true) {
+            id.write_to(dt).unwrap();
+        } else {
             super::reserve_and_delimit(dt, 6);
             dt.extend_from_slice(b"ID:???");
         }
@@ -62,7 +122,18 @@ impl AfterMiddleware for Id {
 }
 
 impl IdHash {
-    fn write_to<W: Write>(_w: W) -> io::Result<()> {
-        unimplemented!();
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        const B64_ENC: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+        let mut buf = [0; 9];
+        let mut hash = self.hash;
+        for b in &mut buf[..8] {
+            *b = B64_ENC[(hash & 0b111111) as usize];
+            hash >>= 6;
+        }
+        buf[8] = self.suffix;
+
+        w.write_all(&buf)
     }
 }
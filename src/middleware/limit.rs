@@ -0,0 +1,187 @@
+//! Enforces the length limits of SETTING.TXT.
+//!
+//! As in 2channel, lengths are counted in bytes of the HTML-escaped
+//! Shift_JIS representation, which is what `Post` holds.
+//!
+//! The name is measured without the tripcode key after `#` and the mail
+//! without the cap password, so that a name does not become too long by
+//! getting a trip or a cap.
+
+use memchr::memchr;
+use typemap::ShareMap;
+
+use super::{BeforeMiddleware, Request, Result};
+use post::Post;
+use setting::Settings;
+use setting::common::{AnchorCount, LineNumber, MailCount, MessageCount, NameCount, SubjectCount};
+
+pub struct Limit;
+
+const DEFAULT_ANCHOR_COUNT: u32 = 10;
+
+// "ＥＲＲＯＲ：サブジェクトが長すぎます！"
+const SUBJECT_TOO_LONG: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x83\x54\x83\x75\x83\x57\x83\x46\x83\x4E\x83\x67\x82\xAA\x92\xB7\x82\xB7\x82\xAC\x82\xDC\x82\xB7\x81\x49";
+// "ＥＲＲＯＲ：名前が長すぎます！"
+const NAME_TOO_LONG: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x96\xBC\x91\x4F\x82\xAA\x92\xB7\x82\xB7\x82\xAC\x82\xDC\x82\xB7\x81\x49";
+// "ＥＲＲＯＲ：メールアドレスが長すぎます！"
+const MAIL_TOO_LONG: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x83\x81\x81\x5B\x83\x8B\x83\x41\x83\x68\x83\x8C\x83\x58\x82\xAA\x92\xB7\x82\xB7\x82\xAC\x82\xDC\x82\xB7\x81\x49";
+// "ＥＲＲＯＲ：本文が長すぎます！"
+const MESSAGE_TOO_LONG: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x96\x7B\x95\xB6\x82\xAA\x92\xB7\x82\xB7\x82\xAC\x82\xDC\x82\xB7\x81\x49";
+// "ＥＲＲＯＲ：改行が多すぎます！"
+const TOO_MANY_LINES: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x89\xFC\x8D\x73\x82\xAA\x91\xBD\x82\xB7\x82\xAC\x82\xDC\x82\xB7\x81\x49";
+// "ＥＲＲＯＲ：レスアンカーリンクが多すぎます！"
+const TOO_MANY_ANCHORS: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x83\x8C\x83\x58\x83\x41\x83\x93\x83\x4A\x81\x5B\x83\x8A\x83\x93\x83\x4E\
+    \x82\xAA\x91\xBD\x82\xB7\x82\xAC\x82\xDC\x82\xB7\x81\x49";
+
+impl BeforeMiddleware for Limit {
+    fn before<'a, 'r, 'b, 'k>(
+        &self, _: &mut ShareMap, post: &Post, _: &Request<'a, 'r, 'b, 'k>, settings: &Settings
+    ) -> Result<'r, ()>
+    {
+        check(post, settings)
+    }
+}
+
+fn check(post: &Post, settings: &Settings) -> Result<'static, ()> {
+    fn exceeds<S>(settings: &Settings, len: usize) -> bool
+        where S: ::setting::Setting<Value=u32>
+    {
+        settings.get::<S>().map_or(false, |&max| len > max as usize)
+    }
+
+    let body = post.body();
+
+    if let Some(title) = post.title() {
+        if exceeds::<SubjectCount>(settings, title.len()) {
+            return Err(SUBJECT_TOO_LONG.into());
+        }
+    }
+    if exceeds::<NameCount>(settings, super::name_end(post.name())) {
+        return Err(NAME_TOO_LONG.into());
+    }
+    let mail = post.mail();
+    let mail_len = memchr(b'#', mail).unwrap_or(mail.len());
+    if exceeds::<MailCount>(settings, mail_len) {
+        return Err(MAIL_TOO_LONG.into());
+    }
+    if exceeds::<MessageCount>(settings, body.len()) {
+        return Err(MESSAGE_TOO_LONG.into());
+    }
+    // 2channel allows twice as many lines as `BBS_LINE_NUMBER`,
+    // which is the number of lines shown in index.html.
+    if let Some(&n) = settings.get::<LineNumber>() {
+        if count_lines(body) > 2 * n as usize {
+            return Err(TOO_MANY_LINES.into());
+        }
+    }
+    let max_anchors = settings.get::<AnchorCount>().cloned().unwrap_or(DEFAULT_ANCHOR_COUNT);
+    if count_anchors(body) > max_anchors as usize {
+        return Err(TOO_MANY_ANCHORS.into());
+    }
+
+    Ok(())
+}
+
+/// Counts lines delimited by either a raw newline or `<br>`.
+fn count_lines(body: &[u8]) -> usize {
+    let mut lines = 1;
+    let mut i = 0;
+    while i < body.len() {
+        if b'\n' == body[i] {
+            lines += 1;
+        } else if body[i..].starts_with(b"<br>") {
+            lines += 1;
+            i += 3;
+        }
+        i += 1;
+    }
+    lines
+}
+
+/// Counts `>>` (escaped as `&gt;&gt;`) followed by a digit.
+fn count_anchors(body: &[u8]) -> usize {
+    const ANCHOR: &[u8] = b"&gt;&gt;";
+
+    let mut count = 0;
+    let mut i = 0;
+    while let Some(j) = memchr(b'&', &body[i..]) {
+        let rest = &body[(i+j)..];
+        if rest.starts_with(ANCHOR) {
+            if rest.get(ANCHOR.len()).map_or(false, u8::is_ascii_digit) {
+                count += 1;
+            }
+            i += j + ANCHOR.len();
+        } else {
+            i += j + 1;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::*;
+    use middleware::Error;
+    use util::temp_dir;
+
+    fn settings(name: &str, txt: &[u8]) -> Settings {
+        let path = temp_dir(name).join("SETTING.TXT");
+        fs::write(&path, txt).unwrap();
+        Settings::load(&File::open(&path).unwrap()).unwrap()
+    }
+
+    fn limit(settings: &Settings, name: &[u8], mail: &[u8], body: &[u8], title: Option<&[u8]>)
+        -> Option<Vec<u8>>
+    {
+        let title = title.map(|t| t.to_vec().into());
+        let post = Post::new(name.to_vec(), mail.to_vec(), body.to_vec(), title);
+        match check(&post, settings) {
+            Ok(()) => None,
+            Err(Error::Invalid(message)) => Some(message.into_owned()),
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn lengths() {
+        let settings = settings("limit-lengths", b"BBS_SUBJECT_COUNT=5\nBBS_NAME_COUNT=5\n\
+            BBS_MAIL_COUNT=5\nBBS_MESSAGE_COUNT=5\n");
+        assert_eq!(None, limit(&settings, b"12345", b"12345", b"12345", Some(b"12345")));
+        assert_eq!(Some(SUBJECT_TOO_LONG.to_vec()), limit(&settings, b"", b"", b"", Some(b"123456")));
+        assert_eq!(Some(NAME_TOO_LONG.to_vec()), limit(&settings, b"123456", b"", b"", None));
+        assert_eq!(Some(MAIL_TOO_LONG.to_vec()), limit(&settings, b"", b"123456", b"", None));
+        // Escaped characters count as they are written to the dat.
+        assert_eq!(Some(MESSAGE_TOO_LONG.to_vec()), limit(&settings, b"", b"", b"&gt;", None));
+    }
+
+    #[test]
+    fn lines_and_anchors() {
+        let settings = settings("limit-lines", b"BBS_LINE_NUMBER=2\nBBS_ANCHOR_COUNT=2\n");
+        assert_eq!(None, limit(&settings, b"", b"", b"1<br>2<br>3<br>4", None));
+        assert_eq!(Some(TOO_MANY_LINES.to_vec()), limit(&settings, b"", b"", b"1<br>2<br>3<br>4\n5", None));
+        assert_eq!(None, limit(&settings, b"", b"", b"&gt;&gt;1 &gt;&gt;2 &gt;&gt;a", None));
+        assert_eq!(
+            Some(TOO_MANY_ANCHORS.to_vec()),
+            limit(&settings, b"", b"", b"&gt;&gt;1 &gt;&gt;2 &gt;&gt;3", None),
+        );
+    }
+
+    #[test]
+    fn keys_and_passwords() {
+        let settings = settings("limit-keys", b"BBS_NAME_COUNT=5\nBBS_MAIL_COUNT=4\n");
+        // Neither the tripcode key nor the cap password counts.
+        assert_eq!(None, limit(&settings, b"12345#abcdefghijkl", b"sage#password", b"", None));
+        // "１２＃key"
+        assert_eq!(None, limit(&settings, b"\x82\x50\x82\x51\x81\x94key", b"", b"", None));
+        assert_eq!(Some(NAME_TOO_LONG.to_vec()), limit(&settings, b"123456#key", b"", b"", None));
+        assert_eq!(Some(MAIL_TOO_LONG.to_vec()), limit(&settings, b"", b"sage2#pass", b"", None));
+    }
+}
@@ -1,8 +1,6 @@
-use std::borrow::Cow;
-
 use typemap::ShareMap;
 
-use super::{AfterMiddleware, BeforeMiddleware, Request};
+use super::{AfterMiddleware, BeforeMiddleware, Request, Result};
 use post::Post;
 use setting::Settings;
 use util::erase_lifetime;
@@ -58,24 +56,43 @@ impl Middlewares {
         }
     }
 
+    /// Runs `BeforeMiddleware::prepare`, whose result is to be passed to
+    /// `apply`.
+    pub fn prepare(&self, post: &Post, settings: &Settings) -> Result<'static, ShareMap> {
+        let mut data = ShareMap::custom();
+        for m in self.before() {
+            m.prepare(&mut data, post, settings)?;
+        }
+        Ok(data)
+    }
+
     pub fn apply<'a, 'r, 'b, 'k>(
         &self,
         mut post: &mut Post,
+        mut data: ShareMap,
         req: &Request<'a, 'r, 'b, 'k>,
         settings: &Settings,
     )
-        -> Result<(), Cow<'r, [u8]>>
+        -> Result<'r, ShareMap>
     {
-        let mut data = ShareMap::custom();
-
         for m in self.before() {
             m.before(&mut data, &post, &req, &settings)?;
         }
+        super::strip_password(post);
+        super::replace_reserved_symbols(post);
         for m in self.after() {
             m.after(&mut post, &data, &settings)?;
         }
 
-        Ok(())
+        Ok(data)
+    }
+
+    /// Tells the after middlewares that a post `apply`'d with `data` has
+    /// been written.
+    pub fn written(&self, post: &Post, data: &ShareMap) {
+        for m in self.after() {
+            m.written(post, data);
+        }
     }
 
     pub fn before(&self) -> &[&(BeforeMiddleware+Send+Sync)] {
@@ -1,19 +1,33 @@
 pub mod cap;
+pub mod confirm;
 pub mod datetime;
+pub mod duplicate;
 pub mod id;
+pub mod limit;
+pub mod noname;
+pub mod samba;
+pub mod token;
+pub mod trip;
 
 mod middlewares;
 
 pub use self::middlewares::Middlewares;
 
 use std::borrow::Cow;
-use std::net::SocketAddr;
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::result;
 
+use rand::{self, Rng};
 use rocket::{self, State};
 use rocket::http::Cookies;
 use typemap::ShareMap;
 
+use bbs::Topic;
+use self::token::Token;
 use post::Post;
 use setting::Settings;
 use validator::{Digits, AlphaNum};
@@ -21,6 +35,10 @@ use validator::{Digits, AlphaNum};
 pub struct Request<'a, 'r: 'a+'b+'k, 'b, 'k> {
     board: AlphaNum<'b>,
     key: Digits<'k>,
+    /// The topic being posted to, which is locked while the middlewares run.
+    topic: &'a Topic,
+    /// The API token that authenticated the post, if any.
+    token: Option<&'a Token>,
     /// The underlying `rocket::Request`, necessary to acquire `State`s.
     rocket: &'a rocket::Request<'r>,
 }
@@ -39,6 +57,14 @@ pub struct Request<'a, 'r: 'a+'b+'k, 'b, 'k> {
 
 // TODO: board id, thread key and post#
 pub trait BeforeMiddleware {
+    /// Called before the topic is locked, for checks too slow to be made
+    /// while the other posts to the board wait, e.g. hashing passwords.
+    fn prepare(&self, _data: &mut ShareMap, _post: &Post, _settings: &Settings)
+        -> Result<'static, ()>
+    {
+        Ok(())
+    }
+
     fn before<'a, 'r, 'b, 'k>(
         &self, data: &mut ShareMap, post: &Post, req: &Request<'a, 'r, 'b, 'k>, settings: &Settings
     ) -> Result<'r, ()>;
@@ -46,15 +72,40 @@ pub trait BeforeMiddleware {
 
 pub trait AfterMiddleware {
     fn after(&self, post: &mut Post, data: &ShareMap, settings: &Settings) -> Result<'static, ()>;
+
+    /// Called once the post has been written to the dat, which is not the
+    /// case for every post that passes `after`.
+    fn written(&self, _post: &Post, _data: &ShareMap) {}
 }
 
-pub type Result<'a, T> = result::Result<T, Cow<'a, [u8]>>;
+pub type Result<'a, T> = result::Result<T, Error<'a>>;
+
+/// The reason why a middleware stopped a post.
+#[derive(Debug)]
+pub enum Error<'a> {
+    /// The post is rejected for its content with a Shift_JIS message.
+    Invalid(Cow<'a, [u8]>),
+    /// The poster is posting too fast or repeatedly, with a Shift_JIS
+    /// message.
+    Throttled(Cow<'a, [u8]>),
+    /// The poster has to confirm the post and submit it again, which is not
+    /// an error from the poster's point of view.
+    Confirm,
+}
 
 impl<'a, 'r, 'b, 'k> Request<'a, 'r, 'b, 'k> {
-    pub fn new(board: AlphaNum<'b>, key: Digits<'k>, rocket: &'a rocket::Request<'r>) -> Self {
+    pub fn new(
+        board: AlphaNum<'b>,
+        key: Digits<'k>,
+        topic: &'a Topic,
+        token: Option<&'a Token>,
+        rocket: &'a rocket::Request<'r>,
+    ) -> Self {
         Request {
             board,
             key,
+            topic,
+            token,
             rocket,
         }
     }
@@ -75,6 +126,14 @@ impl<'a, 'r, 'b, 'k> Request<'a, 'r, 'b, 'k> {
         self.key.as_str()
     }
 
+    pub fn topic(&self) -> &'a Topic {
+        self.topic
+    }
+
+    pub fn token(&self) -> Option<&'a Token> {
+        self.token
+    }
+
     pub fn remote(&self) -> Option<SocketAddr> {
         self.rocket.remote()
     }
@@ -105,6 +164,66 @@ impl<F> AfterMiddleware for F where F: Fn(&mut Post, &ShareMap, &Settings) -> Re
     }
 }
 
+impl<'a> From<&'a [u8]> for Error<'a> {
+    fn from(message: &'a [u8]) -> Self {
+        Error::Invalid(message.into())
+    }
+}
+
+impl<'a> From<Cow<'a, [u8]>> for Error<'a> {
+    fn from(message: Cow<'a, [u8]>) -> Self {
+        Error::Invalid(message)
+    }
+}
+
+const SECRET_LEN: usize = 32;
+
+/// Loads a secret key from `path`, creating the file with a random secret
+/// if it does not exist. The file is only readable by the owner.
+///
+/// A secret shorter than `SECRET_LEN` bytes is rejected as too weak.
+fn load_secret(path: &Path) -> io::Result<Vec<u8>> {
+    match File::open(path) {
+        Ok(mut f) => {
+            let mut secret = Vec::with_capacity(SECRET_LEN);
+            f.read_to_end(&mut secret)?;
+            if secret.len() < SECRET_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "the secret is too short"));
+            }
+            Ok(secret)
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut secret = vec![0; SECRET_LEN];
+            rand::thread_rng().fill(&mut secret[..]);
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut f = options.open(path)?;
+            f.write_all(&secret)?;
+            f.sync_all()?;
+            Ok(secret)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the network of `ip`, assuming that hosts in the same /64 IPv6
+/// network are the same client.
+fn network(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => {
+            let mut net = ip.octets();
+            for b in &mut net[8..] { *b = 0; }
+            net
+        },
+    }
+}
+
 fn reserve_and_delimit(v: &mut Vec<u8>, additional: usize) {
     match v.last() {
         Some(&b' ') | None => v.reserve(additional),
@@ -114,3 +233,154 @@ fn reserve_and_delimit(v: &mut Vec<u8>, additional: usize) {
         },
     }
 }
+
+/// Removes everything after `#` in the mail field, which is the password
+/// of a cap, so that it is never written to the dat.
+///
+/// `Middlewares::apply` calls this before the `AfterMiddleware`s, whether or
+/// not `Cap` is attached.
+fn strip_password(post: &mut Post) {
+    if let Some(i) = post.mail().iter().position(|&c| b'#' == c) {
+        post.mail_mut().truncate(i);
+    }
+}
+
+// "☆"
+const WHITE_STAR: &[u8] = b"\x81\x99";
+// "◇"
+const WHITE_DIAMOND: &[u8] = b"\x81\x9E";
+
+/// Replaces "★" and "◆" typed in the name field with "☆" and "◇",
+/// leaving a tripcode key after `#` intact, so that those symbols can only
+/// come from caps and tripcodes. Character references that browsers show as
+/// them, e.g. `&#9733;`, are replaced as well.
+///
+/// `Middlewares::apply` calls this once before the `AfterMiddleware`s.
+fn replace_reserved_symbols(post: &mut Post) {
+    let end = name_end(post.name());
+    if let Some(mut replaced) = replace_reserved(&post.name()[..end]) {
+        replaced.extend_from_slice(&post.name()[end..]);
+        *post.name_mut() = replaced;
+    }
+}
+
+/// Returns the position of the first `#` or "＃" in `name`, which starts a
+/// tripcode key. The `#` of a numeric character reference, e.g. `&#9733;`
+/// or an emoji passed through by `BBS_UNICODE=pass`, is not one.
+fn name_end(name: &[u8]) -> usize {
+    let mut i = 0;
+    while i < name.len() {
+        match (name[i], name.get(i+1)) {
+            (b'#', _) | (0x81, Some(&0x94)) => return i,
+            (b'&', _) => i += numeric_reference(&name[i..]).map_or(1, |(len, _)| len),
+            (c, _) => i += if is_sjis_lead(c) { 2 } else { 1 },
+        }
+    }
+    name.len()
+}
+
+/// Returns `name` with "★" and "◆" replaced, or `None` if it has neither.
+fn replace_reserved(name: &[u8]) -> Option<Vec<u8>> {
+    let mut ret: Option<Vec<u8>> = None;
+    let mut i = 0;
+    while i < name.len() {
+        let (len, replacement) = match (name[i], name.get(i+1)) {
+            (0x81, Some(&0x9A)) => (2, Some(WHITE_STAR)),
+            (0x81, Some(&0x9F)) => (2, Some(WHITE_DIAMOND)),
+            (b'&', _) => reserved_reference(&name[i..]).map_or((1, None), |(n, r)| (n, Some(r))),
+            (c, _) => (if is_sjis_lead(c) { 2 } else { 1 }, None),
+        };
+        let end = cmp::min(i + len, name.len());
+        match replacement {
+            Some(r) => ret.get_or_insert_with(|| name[..i].to_vec()).extend_from_slice(r),
+            None => if let Some(ref mut v) = ret { v.extend_from_slice(&name[i..end]); },
+        }
+        i = end;
+    }
+    ret
+}
+
+/// Parses a character reference to "★" or "◆" at the beginning of `s`, and
+/// returns its length and the replacement.
+fn reserved_reference(s: &[u8]) -> Option<(usize, &'static [u8])> {
+    const NAMED: &[(&[u8], &[u8])] = &[(b"&starf;", WHITE_STAR), (b"&bigstar;", WHITE_STAR)];
+
+    for &(name, replacement) in NAMED {
+        if s.starts_with(name) {
+            return Some((name.len(), replacement));
+        }
+    }
+
+    match numeric_reference(s) {
+        Some((len, 0x2605)) => Some((len, WHITE_STAR)),
+        Some((len, 0x25C6)) => Some((len, WHITE_DIAMOND)),
+        _ => None,
+    }
+}
+
+/// Parses a numeric character reference at the beginning of `s`, i.e.
+/// `&#NNNN;` or `&#xHHHH;` where browsers also accept a missing `;`, and
+/// returns its length and the code point.
+fn numeric_reference(s: &[u8]) -> Option<(usize, u32)> {
+    if ! s.starts_with(b"&#") {
+        return None;
+    }
+    let (radix, start) = match s.get(2) {
+        Some(&b'x') | Some(&b'X') => (16, 3),
+        _ => (10, 2),
+    };
+    let mut n: u32 = 0;
+    let mut len = start;
+    while let Some(d) = s.get(len).and_then(|&c| (c as char).to_digit(radix)) {
+        n = n.saturating_mul(radix).saturating_add(d);
+        len += 1;
+    }
+    if start == len {
+        return None;
+    }
+    if Some(&b';') == s.get(len) {
+        len += 1;
+    }
+    Some((len, n))
+}
+
+fn is_sjis_lead(c: u8) -> bool {
+    (0x81 <= c && c <= 0x9F) || (0xE0 <= c && c <= 0xFC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_symbols() {
+        // "★a◆"
+        assert_eq!(Some(b"\x81\x99a\x81\x9E".to_vec()), replace_reserved(b"\x81\x9Aa\x81\x9F"));
+        assert_eq!(Some(b"a\x81\x99".to_vec()), replace_reserved(b"a&#9733;"));
+        assert_eq!(Some(b"\x81\x9Eb".to_vec()), replace_reserved(b"&#x25C6;b"));
+        assert_eq!(Some(b"\x81\x9Eb".to_vec()), replace_reserved(b"&#9670b"));
+        assert_eq!(Some(b"\x81\x99".to_vec()), replace_reserved(b"&starf;"));
+        // "☆" and other references are left as they are.
+        assert_eq!(None, replace_reserved(b"\x81\x99&#9734;&amp;&#;"));
+        // Two kanji, 0x8981 and 0x9A40, with the bytes of "★" across them.
+        assert_eq!(None, replace_reserved(b"\x89\x81\x9A\x40"));
+    }
+
+    #[test]
+    fn trip_delimiter() {
+        assert_eq!(1, name_end(b"a#b"));
+        // "a＃b"
+        assert_eq!(1, name_end(b"a\x81\x94b"));
+        assert_eq!(8, name_end(b"&#9670;a#b"));
+        assert_eq!(10, name_end(b"a&#x1F600;"));
+        // Not a reference.
+        assert_eq!(2, name_end(b"a&#b"));
+    }
+
+    #[test]
+    fn reserved_symbols_in_name() {
+        let mut post = Post::new(&b"&#9670;#&#9670;"[..], &b""[..], &b""[..], None);
+        replace_reserved_symbols(&mut post);
+        assert_eq!(b"\x81\x9E#&#9670;", post.name());
+    }
+}
@@ -0,0 +1,43 @@
+use typemap::{Key, ShareMap};
+
+use super::{AfterMiddleware, BeforeMiddleware, Request, Result};
+use post::Post;
+use setting::{self, Settings};
+
+/// A middleware that fills in an empty name with the default name of the
+/// topic or `BBS_NONAME_NAME`.
+pub struct Noname;
+
+impl Key for Noname {
+    /// The default name of the topic.
+    type Value = Box<[u8]>;
+}
+
+// "名無しさん"
+const DEFAULT_NONAME_NAME: &[u8] = b"\x96\xBC\x96\xB3\x82\xB5\x82\xB3\x82\xF1";
+
+impl BeforeMiddleware for Noname {
+    fn before<'a, 'r, 'b, 'k>(
+        &self, data: &mut ShareMap, _: &Post, req: &Request<'a, 'r, 'b, 'k>, _: &Settings
+    ) -> Result<'r, ()>
+    {
+        if let Some(noname) = req.topic().noname() {
+            data.insert::<Noname>(noname.into());
+        }
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for Noname {
+    fn after(&self, post: &mut Post, data: &ShareMap, settings: &Settings) -> Result<'static, ()> {
+        if post.name().is_empty() {
+            let noname = data.get::<Noname>()
+                .map(|n| &**n)
+                .or_else(|| settings.get::<setting::common::NonameName>().map(|n| &**n))
+                .unwrap_or(DEFAULT_NONAME_NAME);
+            post.name_mut().extend_from_slice(noname);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+//! Flood protection in the manner of 2channel's _Samba24_.
+//!
+//! Posts from the same network (the address itself for IPv4, the /64 prefix
+//! for IPv6) to a board must be `BBS_SAMBATIME` seconds apart, and topics
+//! created from it `BBS_THREAD_INTERVAL` seconds apart. A poster who
+//! violates the interval `MAX_STRIKES` times is banned from the board for a
+//! while, and the ban doubles for every further violation. The violations
+//! are forgiven after `FORGIVE_SECS` without another one, counted from the
+//! end of the ban if any.
+//!
+//! The state lives in memory and is lost on restart.
+
+use std::cmp;
+use std::collections::hash_map::{self, HashMap};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use typemap::ShareMap;
+
+use super::{BeforeMiddleware, Error, Request, Result};
+use post::Post;
+use setting::Settings;
+use setting::common::{SambaTime, ThreadInterval};
+
+pub struct Samba {
+    state: Mutex<State>,
+}
+
+struct State {
+    entries: HashMap<Key, Record>,
+    last_eviction: Instant,
+}
+
+struct Record {
+    last_post: Instant,
+    last_topic: Option<Instant>,
+    strikes: u32,
+    /// When `strikes` is reset unless the poster violates the interval again.
+    forgive_at: Instant,
+    banned_until: Option<Instant>,
+}
+
+type Key = (Box<str>, [u8; 16]);
+
+const DEFAULT_SAMBA_TIME: u32 = 10;
+const DEFAULT_THREAD_INTERVAL: u32 = 600;
+
+/// The number of violations before a poster gets banned.
+const MAX_STRIKES: u32 = 3;
+const BASE_PENALTY_SECS: u64 = 10 * 60;
+const MAX_PENALTY_SECS: u64 = 24 * 60 * 60;
+const FORGIVE_SECS: u64 = 60 * 60;
+/// An entry is forgotten when the poster has been quiet for this long.
+const EXPIRY_SECS: u64 = 60 * 60;
+const EVICTION_INTERVAL_SECS: u64 = 10 * 60;
+
+// "ＥＲＲＯＲ：連続投稿ですか？？"
+const TOO_FAST: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x98\x41\x91\xB1\x93\x8A\x8D\x65\x82\xC5\x82\xB7\x82\xA9\x81\x48\x81\x48";
+// "ＥＲＲＯＲ：スレッド立てすぎです。またの機会にどうぞ。。。"
+const TOO_MANY_TOPICS: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x83\x58\x83\x8C\x83\x62\x83\x68\x97\xA7\x82\xC4\x82\xB7\x82\xAC\x82\xC5\x82\xB7\x81\x42\
+    \x82\xDC\x82\xBD\x82\xCC\x8B\x40\x89\xEF\x82\xC9\x82\xC7\x82\xA4\x82\xBC\x81\x42\x81\x42\x81\x42";
+// "ＥＲＲＯＲ：連続投稿のため規制中です。しばらくしてから書き込んでください。"
+const BANNED: &[u8] = b"\x82\x64\x82\x71\x82\x71\x82\x6E\x82\x71\x81\x46\
+    \x98\x41\x91\xB1\x93\x8A\x8D\x65\x82\xCC\x82\xBD\x82\xDF\x8B\x4B\x90\xA7\x92\x86\x82\xC5\x82\xB7\x81\x42\
+    \x82\xB5\x82\xCE\x82\xE7\x82\xAD\x82\xB5\x82\xC4\x82\xA9\x82\xE7\
+    \x8F\x91\x82\xAB\x8D\x9E\x82\xF1\x82\xC5\x82\xAD\x82\xBE\x82\xB3\x82\xA2\x81\x42";
+
+impl Samba {
+    pub fn new() -> Self {
+        Samba {
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                last_eviction: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl Default for Samba {
+    fn default() -> Self {
+        Samba::new()
+    }
+}
+
+impl BeforeMiddleware for Samba {
+    fn before<'a, 'r, 'b, 'k>(
+        &self, _: &mut ShareMap, _: &Post, req: &Request<'a, 'r, 'b, 'k>, settings: &Settings
+    ) -> Result<'r, ()>
+    {
+        // `Id` takes care of requests without a remote address.
+        let addr = match req.remote() {
+            Some(addr) => addr,
+            None => return Ok(()),
+        };
+        let samba = settings.get::<SambaTime>().cloned().unwrap_or(DEFAULT_SAMBA_TIME);
+        let interval = settings.get::<ThreadInterval>().cloned()
+            .unwrap_or(DEFAULT_THREAD_INTERVAL);
+        let creating = 0 == req.topic().post_count();
+        let now = Instant::now();
+
+        let mut state = self.state.lock();
+        state.evict(now);
+
+        let net = super::network(addr.ip());
+        let key = (req.board().to_ascii_lowercase().into_boxed_str(), net);
+        let samba = Duration::from_secs(samba as u64);
+        let interval = Duration::from_secs(interval as u64);
+        state.check(key, now, samba, interval, creating)
+            .map_err(|message| Error::Throttled(message.into()))
+    }
+}
+
+impl State {
+    /// Records an attempt to post from `key` at `now`, returning the error
+    /// message if it is rejected.
+    fn check(&mut self, key: Key, now: Instant, samba: Duration, interval: Duration, creating: bool)
+        -> ::std::result::Result<(), &'static [u8]>
+    {
+        let record = match self.entries.entry(key) {
+            hash_map::Entry::Occupied(e) => e.into_mut(),
+            hash_map::Entry::Vacant(e) => {
+                e.insert(Record {
+                    last_post: now,
+                    last_topic: if creating { Some(now) } else { None },
+                    strikes: 0,
+                    forgive_at: now,
+                    banned_until: None,
+                });
+                return Ok(());
+            },
+        };
+
+        if record.banned_until.map_or(false, |t| now < t) {
+            return Err(BANNED);
+        }
+        if record.forgive_at <= now {
+            record.strikes = 0;
+        }
+
+        let too_fast = now - record.last_post < samba;
+        let too_many = creating && record.last_topic.map_or(false, |t| now - t < interval);
+        // The interval restarts on every attempt, including rejected ones.
+        record.last_post = now;
+        if too_fast || too_many {
+            record.strikes += 1;
+            record.forgive_at = now + Duration::from_secs(FORGIVE_SECS);
+            if record.strikes >= MAX_STRIKES {
+                let doublings = cmp::min(record.strikes - MAX_STRIKES, 16);
+                let penalty = cmp::min(BASE_PENALTY_SECS << doublings, MAX_PENALTY_SECS);
+                let until = now + Duration::from_secs(penalty);
+                record.banned_until = Some(until);
+                record.forgive_at = until + Duration::from_secs(FORGIVE_SECS);
+                return Err(BANNED);
+            }
+            return Err(if too_many { TOO_MANY_TOPICS } else { TOO_FAST });
+        }
+
+        if creating {
+            record.last_topic = Some(now);
+        }
+        Ok(())
+    }
+
+    /// Forgets the posters who have been quiet for `EXPIRY_SECS` and have no
+    /// ban or strikes pending, at most once in `EVICTION_INTERVAL_SECS`.
+    fn evict(&mut self, now: Instant) {
+        if now - self.last_eviction < Duration::from_secs(EVICTION_INTERVAL_SECS) {
+            return;
+        }
+        self.last_eviction = now;
+        self.entries.retain(|_, r| {
+            now - r.last_post < Duration::from_secs(EXPIRY_SECS)
+                || r.banned_until.map_or(false, |t| now < t)
+                || (0 < r.strikes && now < r.forgive_at)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMBA: u64 = 10;
+    const INTERVAL: u64 = 600;
+
+    fn key() -> Key {
+        ("news".into(), [127, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+    }
+
+    fn check(state: &mut State, now: Instant, creating: bool) -> ::std::result::Result<(), &'static [u8]> {
+        let samba = Duration::from_secs(SAMBA);
+        let interval = Duration::from_secs(INTERVAL);
+        state.check(key(), now, samba, interval, creating)
+    }
+
+    fn state(now: Instant) -> State {
+        State { entries: HashMap::new(), last_eviction: now }
+    }
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn too_fast() {
+        let t = Instant::now();
+        let mut state = state(t);
+        assert_eq!(Ok(()), check(&mut state, t, false));
+        assert_eq!(Err(TOO_FAST), check(&mut state, t + secs(SAMBA - 1), false));
+        // The rejected attempt restarts the interval.
+        assert_eq!(Err(TOO_FAST), check(&mut state, t + secs(2 * SAMBA - 2), false));
+        assert_eq!(Ok(()), check(&mut state, t + secs(3 * SAMBA), false));
+    }
+
+    #[test]
+    fn thread_interval() {
+        let t = Instant::now();
+        let mut state = state(t);
+        assert_eq!(Ok(()), check(&mut state, t, true));
+        // Replies are only subject to `BBS_SAMBATIME`.
+        assert_eq!(Ok(()), check(&mut state, t + secs(SAMBA), false));
+        assert_eq!(Err(TOO_MANY_TOPICS), check(&mut state, t + secs(2 * SAMBA), true));
+        assert_eq!(Ok(()), check(&mut state, t + secs(INTERVAL), true));
+    }
+
+    #[test]
+    fn ban_escalation() {
+        let t = Instant::now();
+        let mut state = state(t);
+        let mut now = t;
+        assert_eq!(Ok(()), check(&mut state, now, false));
+        for _ in 1..MAX_STRIKES {
+            now += secs(1);
+            assert_eq!(Err(TOO_FAST), check(&mut state, now, false));
+        }
+        now += secs(1);
+        assert_eq!(Err(BANNED), check(&mut state, now, false));
+        assert_eq!(Err(BANNED), check(&mut state, now + secs(BASE_PENALTY_SECS - 1), false));
+
+        // Another violation after the ban doubles it.
+        now += secs(BASE_PENALTY_SECS);
+        assert_eq!(Ok(()), check(&mut state, now, false));
+        now += secs(1);
+        assert_eq!(Err(BANNED), check(&mut state, now, false));
+        assert_eq!(Err(BANNED), check(&mut state, now + secs(2 * BASE_PENALTY_SECS - 1), false));
+        now += secs(2 * BASE_PENALTY_SECS);
+        assert_eq!(Ok(()), check(&mut state, now, false));
+    }
+
+    #[test]
+    fn forgiveness() {
+        let t = Instant::now();
+        let mut state = state(t);
+        let mut now = t;
+        assert_eq!(Ok(()), check(&mut state, now, false));
+        for _ in 1..MAX_STRIKES {
+            now += secs(1);
+            assert_eq!(Err(TOO_FAST), check(&mut state, now, false));
+        }
+        // The strikes are forgotten after a while.
+        now += secs(FORGIVE_SECS);
+        assert_eq!(Ok(()), check(&mut state, now, false));
+        now += secs(1);
+        assert_eq!(Err(TOO_FAST), check(&mut state, now, false));
+    }
+}
@@ -0,0 +1,180 @@
+//! API tokens for bots posting through the JSON API.
+//!
+//! Tokens are read from a store file with lines of the form:
+//!
+//! ```text
+//! name<>hex(SHA-1(token))<>boards<>quota<>capid<>roles
+//! ```
+//!
+//! `boards` is a comma-separated list of board ids, or `*` for every board.
+//! `quota` is the number of posts allowed per hour, which is unlimited if
+//! empty. `capid` names a cap in the store of `Cap`, under which the posts
+//! are made if present. `roles` is a comma-separated list of extra
+//! permissions, of which only `admin` is defined. An admin token may also
+//! manage the boards it is allowed to post to through the admin routes.
+//!
+//! A post authenticated with a token skips the confirmation by `Confirm`
+//! but goes through the other middlewares as usual.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use sha1::Sha1;
+
+pub struct Tokens {
+    entries: Vec<Token>,
+    /// The start of the current window and the number of posts in it, for
+    /// each token with a quota.
+    usage: Mutex<HashMap<usize, (Instant, u32)>>,
+}
+
+pub struct Token {
+    index: usize,
+    name: Box<str>,
+    hash: Box<[u8]>,
+    /// `None` if the token may post to any board.
+    boards: Option<Vec<Box<str>>>,
+    quota: Option<u32>,
+    cap: Option<Box<[u8]>>,
+    admin: bool,
+}
+
+const QUOTA_WINDOW_SECS: u64 = 60 * 60;
+
+impl Tokens {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Tokens::_load(path.as_ref())
+    }
+
+    fn _load(path: &Path) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let mut fields = line.split("<>");
+            let (name, hash) = match (fields.next(), fields.next()) {
+                (Some(name), Some(hash)) if ! hash.is_empty() => (name, hash),
+                _ => continue,
+            };
+            let boards = match fields.next().unwrap_or("*") {
+                "*" => None,
+                boards => Some(boards.split(',').map(|b| b.trim().into()).collect()),
+            };
+            let quota = fields.next().and_then(|q| q.parse().ok());
+            let cap = fields.next().filter(|c| ! c.is_empty());
+            let admin = fields.next().map_or(false, |roles| {
+                roles.split(',').any(|r| "admin" == r.trim())
+            });
+            entries.push(Token {
+                index: entries.len(),
+                name: name.into(),
+                hash: hash.to_ascii_lowercase().into_bytes().into(),
+                boards,
+                quota,
+                cap: cap.map(|c| c.as_bytes().into()),
+                admin,
+            });
+        }
+        Ok(Tokens {
+            entries,
+            usage: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Hashes a token in the format of the store file.
+    pub fn hash_token(token: &str) -> String {
+        let mut h = Sha1::new();
+        h.update(token.as_bytes());
+        h.digest().to_string()
+    }
+
+    pub fn authenticate(&self, token: &str) -> Option<&Token> {
+        let hash = Tokens::hash_token(token);
+        self.entries.iter().find(|t| hash.as_bytes() == &*t.hash)
+    }
+
+    /// Counts a post about to be made with `token` against its quota,
+    /// returning `false` if the quota is exhausted. The post should be
+    /// `release`d if it fails.
+    pub fn reserve(&self, token: &Token) -> bool {
+        let quota = match token.quota {
+            Some(q) => q,
+            None => return true,
+        };
+        let now = Instant::now();
+        let mut usage = self.usage.lock();
+        let entry = usage.entry(token.index).or_insert((now, 0));
+        if now - entry.0 >= Duration::from_secs(QUOTA_WINDOW_SECS) {
+            *entry = (now, 0);
+        }
+        if entry.1 < quota {
+            entry.1 += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Gives back a post `reserve`d with `token` that has not been made.
+    pub fn release(&self, token: &Token) {
+        if token.quota.is_none() {
+            return;
+        }
+        if let Some(entry) = self.usage.lock().get_mut(&token.index) {
+            // A new window may have started since.
+            entry.1 = entry.1.saturating_sub(1);
+        }
+    }
+}
+
+impl Token {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn allows(&self, board: &str) -> bool {
+        self.boards.as_ref().map_or(true, |boards| {
+            boards.iter().any(|b| b.eq_ignore_ascii_case(board))
+        })
+    }
+
+    /// Returns the id of the cap to post as.
+    pub fn cap(&self) -> Option<&[u8]> {
+        self.cap.as_ref().map(|c| &**c)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.admin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quota() {
+        let tokens = Tokens {
+            entries: vec![Token {
+                index: 0,
+                name: "bot".into(),
+                hash: Tokens::hash_token("secret").into_bytes().into(),
+                boards: None,
+                quota: Some(2),
+                cap: None,
+                admin: false,
+            }],
+            usage: Mutex::new(HashMap::new()),
+        };
+        let token = tokens.authenticate("secret").unwrap();
+        assert!(tokens.reserve(token));
+        assert!(tokens.reserve(token));
+        assert!(! tokens.reserve(token));
+        tokens.release(token);
+        assert!(tokens.reserve(token));
+        assert!(! tokens.reserve(token));
+    }
+}
@@ -0,0 +1,184 @@
+//! Tripcodes compatible with 5channel.
+//!
+//! `name#key` in the name field becomes `name ◆XXXXXXXXXX`, where the trip is
+//! computed as follows:
+//!
+//! - A key shorter than 12 bytes gives the legacy 10-character trip made with
+//!   the traditional DES-based `crypt(3)`.
+//! - A key of 12 bytes or longer gives a 12-character trip made from the
+//!   SHA-1 digest of the key.
+//! - A key of the form `#` + 16 hexadecimal digits + an optional salt of up to
+//!   2 characters (i.e. `##...` in the name field) is a _raw key_, which is
+//!   passed to `crypt(3)` as 8 bytes as is.
+//! - Other keys of 12 bytes or longer starting with `#` or `$` are reserved
+//!   for future extensions and give `???`.
+
+use pwhash::unix_crypt;
+use sha1::Sha1;
+use typemap::ShareMap;
+
+use super::{AfterMiddleware, Result};
+use post::Post;
+use setting::Settings;
+
+pub struct Tripcode;
+
+// " ◆"
+const DIAMOND: &[u8] = b" \x81\x9F";
+
+impl AfterMiddleware for Tripcode {
+    fn after(&self, post: &mut Post, _: &ShareMap, _: &Settings) -> Result<'static, ()> {
+        let end = super::name_end(post.name());
+        if end == post.name().len() {
+            return Ok(());
+        }
+
+        let name = post.name_mut();
+        // "＃" is 2 bytes long.
+        let delim = if b'#' == name[end] { 1 } else { 2 };
+        let trip = tripcode(&name[(end+delim)..]);
+
+        name.truncate(end);
+        if name.is_empty() {
+            name.extend_from_slice(&DIAMOND[1..]);
+        } else {
+            name.extend_from_slice(DIAMOND);
+        }
+        name.extend_from_slice(&trip);
+
+        Ok(())
+    }
+}
+
+/// Computes the trip of `key`, excluding the leading "◆".
+pub fn tripcode(key: &[u8]) -> Vec<u8> {
+    const UNSUPPORTED: &[u8] = b"???";
+
+    if key.len() < 12 {
+        return des_trip(key, &salt(key));
+    }
+
+    match key[0] {
+        b'#' => {
+            if key.len() < 17 || 19 < key.len() {
+                return UNSUPPORTED.to_owned();
+            }
+            let salt = &key[17..];
+            let valid = key[1..17].iter().all(u8::is_ascii_hexdigit)
+                && salt.iter().all(|&c| is_salt_char(c));
+            if ! valid {
+                return UNSUPPORTED.to_owned();
+            }
+
+            let mut raw = [0; 8];
+            for (i, b) in raw.iter_mut().enumerate() {
+                *b = hex(key[2*i+1]) << 4 | hex(key[2*i+2]);
+            }
+            let mut s = [b'.'; 2];
+            s[..salt.len()].copy_from_slice(salt);
+            des_trip(&raw, &s)
+        },
+        b'$' => UNSUPPORTED.to_owned(),
+        _ => {
+            let mut h = Sha1::new();
+            h.update(key);
+            let mut trip = base64(&h.digest().bytes()[..9]);
+            for c in &mut trip {
+                if b'+' == *c { *c = b'.'; }
+            }
+            trip
+        },
+    }
+}
+
+fn des_trip(key: &[u8], salt: &[u8; 2]) -> Vec<u8> {
+    // `crypt(3)` stops at a NUL byte.
+    let key = match key.iter().position(|&c| 0 == c) {
+        Some(i) => &key[..i],
+        None => key,
+    };
+    let salt = unsafe { ::std::str::from_utf8_unchecked(salt) };
+    // `salt` consists only of valid salt characters, so this never fails.
+    let hash = unix_crypt::hash_with(salt, key).expect("invalid salt");
+    hash.as_bytes()[(hash.len()-10)..].to_owned()
+}
+
+/// Makes the salt from a key in the traditional way:
+/// `substr($key."H.", 1, 2) =~ s/[^\.-z]/./gr =~ tr/:;<=>?@[\\]^_`/ABCDEFGabcdef/r`.
+fn salt(key: &[u8]) -> [u8; 2] {
+    let mut salt = [b'.'; 2];
+    for (s, &c) in salt.iter_mut().zip(key.iter().chain(b"H.").skip(1)) {
+        *s = match c {
+            b':'..=b'@' => c - b':' + b'A',
+            b'['..=b'`' => c - b'[' + b'a',
+            b'.'..=b'z' => c,
+            _ => b'.',
+        };
+    }
+    salt
+}
+
+fn is_salt_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b'.' == c || b'/' == c
+}
+
+fn hex(c: u8) -> u8 {
+    match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        _ => c - b'A' + 10,
+    }
+}
+
+fn base64(bytes: &[u8]) -> Vec<u8> {
+    const B64_ENC: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut ret = Vec::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..(chunk.len() + 1) {
+            ret.push(B64_ENC[(n >> (18 - 6 * i) & 0b111111) as usize]);
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn des() {
+        assert_eq!(b"/WG5qp963c", &*tripcode(b"istrip"));
+        assert_eq!(b"WokonZwxw2", &*tripcode(b"tea"));
+        assert_eq!(b"ZnBI2EKkq.", &*tripcode(b"a"));
+    }
+
+    #[test]
+    fn sha1() {
+        assert_eq!(b"60YIzr/P1N.B", &*tripcode(b"abcdefghijkl"));
+    }
+
+    #[test]
+    fn raw_key() {
+        assert_eq!(b"CSfBRAV1M6", &*tripcode(b"#0123456789abcdefab"));
+        assert_eq!(b"???", &*tripcode(b"#0123456789abcdeg"));
+        assert_eq!(b"???", &*tripcode(b"$abcdefghijkl"));
+    }
+
+    fn trip(name: &[u8]) -> Vec<u8> {
+        let mut post = Post::new(name, &b""[..], &b""[..], None);
+        Tripcode.after(&mut post, &ShareMap::custom(), &Settings::empty()).unwrap();
+        post.name().to_vec()
+    }
+
+    #[test]
+    fn references_in_name() {
+        // An emoji passed through by `BBS_UNICODE=pass` is not a key.
+        assert_eq!(b"a&#128512;".to_vec(), trip(b"a&#128512;"));
+        assert_eq!(b"&#x2605; \x81\x9FWokonZwxw2".to_vec(), trip(b"&#x2605;#tea"));
+        assert_eq!(b"&amp; \x81\x9FWokonZwxw2".to_vec(), trip(b"&amp;#tea"));
+    }
+}
@@ -44,6 +44,12 @@ impl<'r> Post<'r> {
         self.mail.to_mut()
     }
 
+    /// Returns whether the mail field contains `sage`, which keeps the topic
+    /// from being bumped and hides the ID.
+    pub fn is_sage(&self) -> bool {
+        self.mail.windows(4).any(|w| w == b"sage")
+    }
+
     #[inline]
     pub fn datetime(&self) -> &[u8] {
         &self.datetime
@@ -1,25 +1,42 @@
+use std::cmp;
 use std::fs;
+use std::io::{self, Read};
 use std::ops::Deref;
 use std::str::{self, FromStr};
+use std::sync::Arc;
 
 use rocket::http::{Header, Status};
 use rocket::request::Request;
-use rocket::response::{Responder, Response};
+use rocket::response::{Responder, Response, ResponseBuilder};
 use time::{self, Timespec};
 
+macro_rules! h {
+    ($name:ident) => {
+        <::hyper::header::$name as ::hyper::header::Header>::header_name()
+    };
+}
+
 #[derive(Clone, Default)]
 pub struct Cacheable<T> {
     body: T,
     metadata: Metadata,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct Metadata {
     etag: Box<str>,
     modified: Box<str>,
     mtime: Timespec,
 }
 
+/// A reader over a range of the body of a shared `Cacheable`,
+/// which lets an `Arc<Cacheable<T>>` be sent without copying the body.
+struct ArcSlice<T> {
+    inner: Arc<Cacheable<T>>,
+    pos: usize,
+    end: usize,
+}
+
 /// Yet another implementation of `hyper::header::ByteRangeSpec`
 /// because that only supports `u64` range whereas we need `usize`.
 struct ByteRangesSpecifier<T>(T, Option<T>);
@@ -41,6 +58,14 @@ impl<T> Cacheable<T> {
     pub fn body_mut(&mut self) -> &mut T {
         &mut self.body
     }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn into_body(self) -> T {
+        self.body
+    }
 }
 
 impl<T> Deref for Cacheable<T> {
@@ -53,7 +78,30 @@ impl<T> Deref for Cacheable<T> {
 
 impl<'r, T> Responder<'r> for &'r Cacheable<T> where T: AsRef<[u8]> {
     fn respond_to(self, req: &Request) -> Result<Response<'r>, Status> {
-        respond_to(req, self.body.as_ref(), &self.metadata)
+        let body = self.body.as_ref();
+        let mut res = Response::build();
+        if let Some((s, e)) = evaluate(req, &mut res, body.len(), &self.metadata) {
+            super::slice_body(&mut res, &body[s..e])
+                .header(Header::new(h!(ETag), &*self.metadata.etag))
+                .header(Header::new(h!(LastModified), &*self.metadata.modified));
+        }
+        res.ok()
+    }
+}
+
+impl<'r, T> Responder<'r> for Arc<Cacheable<T>> where T: AsRef<[u8]>+'r {
+    fn respond_to(self, req: &Request) -> Result<Response<'r>, Status> {
+        let mut res = Response::build();
+        let range = evaluate(req, &mut res, self.body.as_ref().len(), &self.metadata);
+        if let Some((pos, end)) = range {
+            let etag = String::from(&*self.metadata.etag);
+            let modified = String::from(&*self.metadata.modified);
+            let body = ArcSlice { inner: self, pos, end };
+            super::reader_body(&mut res, body, end - pos)
+                .header(Header::new(h!(ETag), etag))
+                .header(Header::new(h!(LastModified), modified));
+        }
+        res.ok()
     }
 }
 
@@ -71,6 +119,15 @@ impl Metadata {
         self.set_etag(id);
     }
 
+    /// Returns the metadata of another representation of the same resource,
+    /// which has the same mtime but a different ETag for each `salt`.
+    pub fn variant(&self, salt: u64) -> Self {
+        let id = self.etag.bytes().fold(salt, |h, b| h.rotate_left(8) ^ b as u64);
+        let mut ret = self.clone();
+        ret.set_etag(id);
+        ret
+    }
+
     fn new(id: u64, mtime: Timespec) -> Self {
         unsafe fn alloc_boxed_str(cap: usize) -> Box<str> {
             let mut buf = String::with_capacity(cap);
@@ -103,10 +160,11 @@ impl Metadata {
         const B64_ENC: &[u8; 64] =
             b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 
-        let mut hash = id.overflowing_mul(self.mtime.sec as u64).0;
+        let mut hash = id.overflowing_mul(self.mtime.sec as u64).0
+            ^ self.mtime.nsec as u64;
         unsafe {
             for b in &mut self.etag.as_bytes_mut()[1..ETAG_INNER_LEN+1] {
-                *b = B64_ENC[(hash | 0b111111) as usize];
+                *b = B64_ENC[(hash & 0b111111) as usize];
                 hash >>= 6;
             }
         }
@@ -135,9 +193,21 @@ impl Default for Metadata {
     }
 }
 
+/// The length of the file is mixed into the ETag so that appending to
+/// a file within the resolution of its mtime still changes the ETag.
 impl<'a> From<&'a fs::Metadata> for Metadata {
     fn from(m: &'a fs::Metadata) -> Self {
-        Metadata::new(metadata::id(m), metadata::mtime(m))
+        Metadata::new(metadata::id(m) ^ m.len(), metadata::mtime(m))
+    }
+}
+
+impl<T: AsRef<[u8]>> Read for ArcSlice<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len(), self.end - self.pos);
+        let src = &self.inner.body.as_ref()[self.pos..(self.pos+n)];
+        buf[..n].copy_from_slice(src);
+        self.pos += n;
+        Ok(n)
     }
 }
 
@@ -158,29 +228,32 @@ impl<T: Copy+FromStr+PartialOrd> FromStr for ByteRangesSpecifier<T> {
     }
 }
 
-fn respond_to<'r>(req: &Request, body: &'r [u8], metadata: &'r Metadata)
-    -> Result<Response<'r>, Status>
+/// Evaluates the conditional and `Range` headers of `req` against a body of
+/// length `len`.
+///
+/// Returns the byte range of the body to be sent, or `None` if the response
+/// is complete without a body.
+fn evaluate(req: &Request, res: &mut ResponseBuilder, len: usize, metadata: &Metadata)
+    -> Option<(usize, usize)>
 {
     use rocket::http::hyper::header::*;
 
-    macro_rules! h {
-        ($name:ident) => {
-            <::hyper::header::$name as ::hyper::header::Header>::header_name()
-        };
-    }
-
     let headers = req.headers();
-    let mut res = Response::build();
     res.header(Header::new(h!(AcceptRanges), "bytes"));
 
-    if headers.get(h!(IfNoneMatch)).any(|v| *v == *metadata.etag) {
-        return res.status(Status::NotModified).ok();
-    }
-
-    if let Some(ims) = headers.get_one(h!(IfModifiedSince)) {
+    if headers.contains(h!(IfNoneMatch)) {
+        if headers.get(h!(IfNoneMatch)).any(|v| *v == *metadata.etag) {
+            res.status(Status::NotModified);
+            return None;
+        }
+    } else if let Some(ims) = headers.get_one(h!(IfModifiedSince)) {
+        // https://tools.ietf.org/html/rfc7232#section-6
+        // If-Modified-Since is only consulted without If-None-Match, since
+        // its whole seconds cannot tell apart posts within the same second.
         if let Ok(HttpDate(tm)) = ims.parse() {
-            if tm.to_timespec().sec < metadata.mtime.sec {
-                return res.status(Status::NotModified).ok();
+            if metadata.mtime.sec <= tm.to_timespec().sec {
+                res.status(Status::NotModified);
+                return None;
             }
         }
     }
@@ -188,26 +261,26 @@ fn respond_to<'r>(req: &Request, body: &'r [u8], metadata: &'r Metadata)
     if headers.contains(h!(Range)) {
         let mut values = headers.get(h!(Range));
 
-        let val = values.next().ok_or(Status::RangeNotSatisfiable)?;
+        let val = values.next().expect("`contains` returned `true`");
         if values.next().is_some() || ! val.starts_with("bytes=") {
-            return res.status(Status::RangeNotSatisfiable).ok();
+            res.status(Status::RangeNotSatisfiable);
+            return None;
         }
 
-        let slice = match val[6..].parse() {
-            Ok(ByteRangesSpecifier(s, Some(e))) if e < body.len() =>
-                &body[s..=e],
-            Ok(ByteRangesSpecifier(s, None)) if s < body.len() =>
-                &body[s..],
-            _ => return res.status(Status::RangeNotSatisfiable).ok(),
+        let range = match val[6..].parse() {
+            Ok(ByteRangesSpecifier(s, Some(e))) if e < len => (s, e + 1),
+            Ok(ByteRangesSpecifier(s, None)) if s < len => (s, len),
+            _ => {
+                res.status(Status::RangeNotSatisfiable);
+                return None;
+            },
         };
 
-        super::slice_body(&mut res, slice).status(Status::PartialContent)
+        res.status(Status::PartialContent);
+        Some(range)
     } else {
-        super::slice_body(&mut res, body)
+        Some((0, len))
     }
-        .header(Header::new(h!(ETag), &*metadata.etag))
-        .header(Header::new(h!(LastModified), &*metadata.modified))
-        .ok()
 }
 
 mod metadata {
@@ -53,6 +53,10 @@ impl<T> StaticFile<T> {
             tag: PhantomData,
         })
     }
+
+    pub fn into_inner(self) -> Cacheable<Box<[u8]>> {
+        self.inner
+    }
 }
 
 impl<T> AsRef<[u8]> for StaticFile<T> {
@@ -77,6 +81,13 @@ impl<'r, T> Responder<'r> for &'r StaticFile<T> {
 
 fn slice_body<'a, 'r>(res: &'a mut ResponseBuilder<'r>, slice: &'r [u8])
     -> &'a mut ResponseBuilder<'r>
+{
+    reader_body(res, slice, slice.len())
+}
+
+fn reader_body<'a, 'r, B>(res: &'a mut ResponseBuilder<'r>, body: B, len: usize)
+    -> &'a mut ResponseBuilder<'r>
+    where B: Read+'r
 {
     cfg_if! {
         if #[cfg(any(
@@ -93,10 +104,10 @@ fn slice_body<'a, 'r>(res: &'a mut ResponseBuilder<'r>, slice: &'r [u8])
         }
     }
 
-    let len = u64_from_usize(slice.len());
+    let len = u64_from_usize(len);
     if len <= DEFAULT_CHUNK_SIZE {
-        res.raw_body(Body::Sized(slice, len))
+        res.raw_body(Body::Sized(body, len))
     } else {
-        res.streamed_body(slice)
+        res.streamed_body(body)
     }
 }
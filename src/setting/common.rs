@@ -73,6 +73,26 @@ mona_settings! {
     ForceId("BBS_FORCE_ID") -> bool;
     NoId("BBS_NO_ID") -> bool;
     Heisa("BBS_HEISA") -> bool;
+    ThreadNumber("BBS_THREAD_NUMBER") -> u32;
+    ContentsNumber("BBS_CONTENTS_NUMBER") -> u32;
+    MaxMenuThread("BBS_MAX_MENU_THREAD") -> u32;
+    // Replies to a topic with this many posts no longer bump the topic.
+    SageCount("BBS_SAGE_COUNT") -> u32;
+    MaxRes("BBS_MAX_RES") -> u32;
+    DatMaxKb("BBS_DAT_MAX_KB") -> u32;
+    // The oldest topics are archived when a new topic exceeds this number.
+    MaxThread("BBS_MAX_THREAD") -> u32;
+    // The maximum number of `>>` anchors in a post.
+    AnchorCount("BBS_ANCHOR_COUNT") -> u32;
+    // The minimum seconds between posts from the same address.
+    SambaTime("BBS_SAMBATIME") -> u32;
+    // The minimum seconds between topics created from the same address.
+    ThreadInterval("BBS_THREAD_INTERVAL") -> u32;
+    // Posts from the same address with the same body within this many
+    // seconds are rejected as duplicates.
+    DuplicateTime("BBS_DUPLICATE_TIME") -> u32;
+    // Whether duplicates are also looked for in the other topics of the board.
+    DuplicateBoard("BBS_DUPLICATE_BOARD") -> bool;
 }
 
 pub enum Adult {}
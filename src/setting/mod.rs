@@ -2,9 +2,12 @@ pub mod common;
 
 use std::any::Any;
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
 use std::default::Default;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::sync::Arc;
 
 use lazy_init::LazyTransform;
 use memchr::memchr;
@@ -12,12 +15,14 @@ use rocket::http::Status;
 use rocket::request::Request;
 use rocket::response::{Responder, Response};
 
-use responder::StaticFile;
+use responder::{Cacheable, Metadata, StaticFile};
 use util::erase_lifetime;
 
 #[derive(Default)]
 pub struct Settings {
-    text: StaticFile,
+    /// Shared with the responses of SETTING.TXT, which may outlive the
+    /// settings when they are reloaded.
+    text: Arc<Cacheable<Box<[u8]>>>,
     map: HashMap<&'static [u8], Item<'static>>,
 }
 
@@ -38,10 +43,16 @@ impl Settings {
     }
 
     pub fn load(text: &File) -> io::Result<Self> {
-        let text = StaticFile::new(&text)?;
+        let file = StaticFile::<()>::new(&text)?.into_inner();
+        // The metadata of the file alone would give the same ETag to an edit
+        // that keeps the length within the resolution of the mtime.
+        let mut h = DefaultHasher::new();
+        file.body().hash(&mut h);
+        let metadata = file.metadata().variant(h.finish());
+        let text = Arc::new(Cacheable::new(file.into_body(), metadata));
         let mut map = HashMap::new();
         {
-            let mut slice: &[u8] = text.as_ref();
+            let mut slice: &[u8] = text.body();
             while ! slice.is_empty() {
                 let line = if let Some(i) = memchr(b'\n', slice) {
                     unsafe {
@@ -72,17 +83,28 @@ impl Settings {
     pub fn get<S: Setting>(&self) -> Option<&S::Value> {
         self.map.get(S::KEY.as_bytes()).and_then(Item::typed::<S>)
     }
+
+    /// Returns the metadata of the file the settings have been loaded from,
+    /// whose ETag also depends on the content of the file.
+    pub fn metadata(&self) -> &Metadata {
+        self.text.metadata()
+    }
+
+    /// Returns the text of SETTING.TXT as a response.
+    pub fn text(&self) -> Arc<Cacheable<Box<[u8]>>> {
+        Arc::clone(&self.text)
+    }
 }
 
 impl AsRef<[u8]> for Settings {
     fn as_ref(&self) -> &[u8] {
-        self.text.as_ref()
+        self.text.body()
     }
 }
 
 impl<'r> Responder<'r> for &'r Settings {
     fn respond_to(self, req: &Request) -> Result<Response<'r>, Status> {
-        self.text.respond_to(req)
+        (&*self.text).respond_to(req)
     }
 }
 
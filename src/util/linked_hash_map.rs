@@ -161,6 +161,22 @@ impl<K: Hash+Eq+Copy, V> LinkedHashMap<K, V> {
 }
 
 impl<K: Copy, V> LinkedHashMap<K, V> {
+    /// Returns the entry at the back of the list, i.e. the one that has not
+    /// been inserted or bumped for the longest time.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut map = LinkedHashMap::new();
+    /// map.insert(1, "1");
+    /// map.insert(2, "2");
+    /// map.bump(1);
+    /// assert_eq!(Some((2, &"2")), map.back());
+    /// ```
+    pub fn back(&self) -> Option<(K, &V)> {
+        self.list.back().map(|&(k, ref v)| (k, v))
+    }
+
     /// Returns an iterator visiting all key-value pairs from the front of
     /// the list.
     ///
@@ -3,6 +3,21 @@ mod unsafe_linked_list;
 
 pub use self::linked_hash_map::LinkedHashMap;
 
+#[cfg(test)]
+use std::path::PathBuf;
+
 pub unsafe fn erase_lifetime<'a, T: ?Sized>(t: &T) -> &'a T {
     ::std::mem::transmute::<&T, &'a T>(t)
 }
+
+/// Makes an empty directory named after `name` in the temporary directory,
+/// for tests that need files.
+#[cfg(test)]
+pub fn temp_dir(name: &str) -> PathBuf {
+    use std::{env, fs, process};
+
+    let dir = env::temp_dir().join(format!("monaxide-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -4,6 +4,7 @@ use std::ptr::NonNull;
 /// A doubly-linked list that provides unsafe access to inner nodes.
 pub struct UnsafeLinkedList<T> {
     head: Option<NonNull<Node<T>>>,
+    tail: Option<NonNull<Node<T>>>,
     marker: PhantomData<Box<Node<T>>>,
 }
 
@@ -28,6 +29,7 @@ impl<T> UnsafeLinkedList<T> {
     pub fn new() -> Self {
         UnsafeLinkedList {
             head: None,
+            tail: None,
             marker: PhantomData,
         }
     }
@@ -72,6 +74,11 @@ impl<T> UnsafeLinkedList<T> {
         &mut (*node.ptr.as_ptr()).elm
     }
 
+    /// Returns a reference to the last element of the list.
+    pub fn back(&self) -> Option<&T> {
+        self.tail.map(|n| unsafe { &(*n.as_ptr()).elm })
+    }
+
     pub fn iter(&self) -> Iter<T> {
         Iter {
             node: self.head,
@@ -94,6 +101,8 @@ impl<T> UnsafeLinkedList<T> {
             unsafe {
                 head.as_mut().prev = Some(ptr);
             }
+        } else {
+            self.tail = Some(ptr);
         }
 
         self.head = Some(ptr);
@@ -108,6 +117,9 @@ impl<T> UnsafeLinkedList<T> {
         if let Some(mut next) = node.next {
             debug_assert_eq!(Some(ptr), next.as_ref().prev);
             next.as_mut().prev = node.prev;
+        } else {
+            debug_assert_eq!(Some(ptr), self.tail);
+            self.tail = node.prev;
         }
 
         if let Some(mut prev) = node.prev {
@@ -191,4 +203,22 @@ mod tests {
         drop(node);
         assert_eq!(count.0.get(), 3);
     }
+
+    #[test]
+    fn back() {
+        let mut list = UnsafeLinkedList::new();
+        assert_eq!(None, list.back());
+
+        let first = list.push_front(1);
+        let second = list.push_front(2);
+        list.push_front(3);
+        assert_eq!(Some(&1), list.back());
+
+        unsafe {
+            list.bump(&first);
+            assert_eq!(Some(&2), list.back());
+            list.remove(second);
+        }
+        assert_eq!(Some(&3), list.back());
+    }
 }
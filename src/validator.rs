@@ -16,9 +16,12 @@ pub struct Digits<'v> {
     raw: &'v str,
 }
 
-/// HTML-escaped byte string
+/// HTML-escaped byte string without newlines
 pub struct Escaped<'v>(Cow<'v, [u8]>);
 
+/// HTML-escaped byte string whose newlines are ` <br> ` as in a dat
+pub struct EscapedLines<'v>(Cow<'v, [u8]>);
+
 impl<'v> AlphaNum<'v> {
     pub fn as_str(&self) -> &'v str {
         self.0
@@ -69,41 +72,27 @@ impl<'v> FromFormValue<'v> for Digits<'v> {
     }
 }
 
+impl<'v> FromParam<'v> for Digits<'v> {
+    type Error = &'v RawStr;
+
+    fn from_param(v: &'v RawStr) -> Result<Self, &'v RawStr> {
+        FromFormValue::from_form_value(v)
+    }
+}
+
 impl<'v> FromFormValue<'v> for Escaped<'v> {
     type Error = !;
 
     fn from_form_value(v: &'v RawStr) -> Result<Self, !> {
-        fn html_escape(src: Vec<u8>) -> Vec<u8> {
-            let mut ret: Option<Vec<u8>> = None;
-
-            for (i, &c) in src.iter().enumerate() {
-                let esc: &[u8] = match c {
-                    // These rules are not enough for general HTML escaping,
-                    // but this is what 2channel does, so we adopt them
-                    // as they are for compatibility.
-                    b'"' => b"&quot;",
-                    b'<' => b"&lt;",
-                    b'>' => b"&gt;",
-                    _ => {
-                        ret.as_mut().map(|v| v.push(c));
-                        continue;
-                    },
-                };
-                ret.get_or_insert_with(|| {
-                    let mut vec = Vec::with_capacity(src.len() * 2);
-                    vec.extend_from_slice(&src[..i]);
-                    vec
-                }).extend_from_slice(esc);
-            }
-
-            ret.unwrap_or(src)
-        }
+        Ok(Escaped(escape_form_value(v.as_bytes(), b"")))
+    }
+}
 
-        let cow = percent_decode(v.as_bytes())
-            .if_any()
-            .map(|owned| html_escape(owned).into())
-            .unwrap_or_else(|| v.as_bytes().into()); // TODO: escaping
-        Ok(Escaped(cow))
+impl<'v> FromFormValue<'v> for EscapedLines<'v> {
+    type Error = !;
+
+    fn from_form_value(v: &'v RawStr) -> Result<Self, !> {
+        Ok(EscapedLines(escape_form_value(v.as_bytes(), b" <br> ")))
     }
 }
 
@@ -125,6 +114,12 @@ impl<'v> AsRef<[u8]> for Escaped<'v> {
     }
 }
 
+impl<'v> AsRef<[u8]> for EscapedLines<'v> {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl<'v> Deref for AlphaNum<'v> {
     type Target = str;
 
@@ -141,12 +136,26 @@ impl<'v> Deref for Escaped<'v> {
     }
 }
 
+impl<'v> Deref for EscapedLines<'v> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_ref()
+    }
+}
+
 impl<'v> From<Escaped<'v>> for Cow<'v, [u8]> {
     fn from(e: Escaped<'v>) -> Self {
         e.0
     }
 }
 
+impl<'v> From<EscapedLines<'v>> for Cow<'v, [u8]> {
+    fn from(e: EscapedLines<'v>) -> Self {
+        e.0
+    }
+}
+
 impl<'v> Deref for Digits<'v> {
     type Target = str;
 
@@ -155,6 +164,64 @@ impl<'v> Deref for Digits<'v> {
     }
 }
 
+/// Decodes an `application/x-www-form-urlencoded` value and escapes it,
+/// replacing each of `\r\n`, `\n` and `\r` with `newline`.
+fn escape_form_value<'v>(raw: &'v [u8], newline: &[u8]) -> Cow<'v, [u8]> {
+    let plain = raw.iter().all(|&c| match c {
+        b'%' | b'+' | b'"' | b'<' | b'>' | b'\r' | b'\n' => false,
+        _ => true,
+    });
+    if plain {
+        return raw.into();
+    }
+
+    let spaced: Vec<u8> = raw.iter().map(|&c| if b'+' == c { b' ' } else { c }).collect();
+    let escaped = html_escape(percent_decode(&spaced).collect());
+    let mut ret = Vec::with_capacity(escaped.len());
+    let mut i = 0;
+    while i < escaped.len() {
+        match escaped[i] {
+            b'\r' => {
+                ret.extend_from_slice(newline);
+                if Some(&b'\n') == escaped.get(i + 1) {
+                    i += 1;
+                }
+            },
+            b'\n' => ret.extend_from_slice(newline),
+            c => ret.push(c),
+        }
+        i += 1;
+    }
+    ret.into()
+}
+
+/// Escapes `"`, `<` and `>` as 2channel does.
+pub fn html_escape(src: Vec<u8>) -> Vec<u8> {
+    let mut ret: Option<Vec<u8>> = None;
+
+    for (i, &c) in src.iter().enumerate() {
+        let esc: &[u8] = match c {
+            // These rules are not enough for general HTML escaping,
+            // but this is what 2channel does, so we adopt them
+            // as they are for compatibility.
+            b'"' => b"&quot;",
+            b'<' => b"&lt;",
+            b'>' => b"&gt;",
+            _ => {
+                ret.as_mut().map(|v| v.push(c));
+                continue;
+            },
+        };
+        ret.get_or_insert_with(|| {
+            let mut vec = Vec::with_capacity(src.len() * 2);
+            vec.extend_from_slice(&src[..i]);
+            vec
+        }).extend_from_slice(esc);
+    }
+
+    ret.unwrap_or(src)
+}
+
 pub fn is_alphanum(c: u8) -> bool {
     (b'a' <= c && c <= b'z') || (b'A' <= c && c <= b'Z') || is_digit(c)
 }
@@ -162,3 +229,21 @@ pub fn is_alphanum(c: u8) -> bool {
 pub fn is_digit(c: u8) -> bool {
     b'0' <= c && c <= b'9'
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn form_values() {
+        assert_eq!(&b"abc"[..], &*escape_form_value(b"abc", b""));
+        assert_eq!(&b"a b&lt;&gt;&quot;"[..], &*escape_form_value(b"a+b<>%22", b""));
+        assert_eq!(&b"a+b"[..], &*escape_form_value(b"a%2Bb", b""));
+        // Every kind of newline is replaced.
+        assert_eq!(
+            &b"a <br> b <br> c <br> d"[..],
+            &*escape_form_value(b"a%0D%0Ab%0Ac%0Dd", b" <br> "),
+        );
+        assert_eq!(&b"ab"[..], &*escape_form_value(b"a%0D%0A\nb", b""));
+    }
+}